@@ -0,0 +1,151 @@
+//! Parses the signature algorithm names that keystore uses, such as "SHA256withECDSA".
+//! See the full list at the Java documentation for [Signature algorithms].
+//! Keystore has no name for PSS over a hash calculated by the application, so
+//! tergent names it after the digest of that hash, e.g. "NONEwithRSA/PSS/SHA256".
+//!
+//! [Signature algorithms]:
+//! https://docs.oracle.com/javase/8/docs/technotes/guides/security/StandardNames.html#Signature
+
+use rsa::{Pkcs1v15Sign, Pss};
+use sha1::Sha1;
+use sha2::{Digest as _, Sha224, Sha256, Sha384, Sha512};
+
+/// A signature algorithm, made of a digest and a signature scheme.
+pub struct Algorithm {
+    pub digest: Digest,
+    pub scheme: Scheme,
+}
+
+/// Digest part of a signature algorithm, e.g. "SHA256" in "SHA256withECDSA".
+#[derive(Clone, Copy)]
+pub enum Digest {
+    None,
+    Sha1,
+    Sha224,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+/// Signature scheme part of a signature algorithm, e.g. "ECDSA" in "SHA256withECDSA".
+#[derive(Clone, Copy)]
+pub enum Scheme {
+    RsaPkcs1,
+    /// PSS padding, using the given digest for the encoding and for MGF1. This is the
+    /// digest of the algorithm, unless the data has been hashed by the application.
+    RsaPss(Digest),
+    Ecdsa,
+}
+
+impl Algorithm {
+    /// Parses the name of a signature algorithm. Returns `None` if it is not supported.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let (digest, scheme) = name.split_once("with")?;
+        let digest = Digest::from_name(digest)?;
+        let scheme = match (scheme, digest) {
+            ("RSA", _) => Scheme::RsaPkcs1,
+            ("ECDSA", _) => Scheme::Ecdsa,
+            // PSS cannot be used without a digest, unless the data is already hashed.
+            ("RSA/PSS", Digest::None) => return None,
+            ("RSA/PSS", _) => Scheme::RsaPss(digest),
+            (scheme, Digest::None) => {
+                let hash = scheme.strip_prefix("RSA/PSS/")?;
+                match Digest::from_name(hash)? {
+                    Digest::None => return None,
+                    hash => Scheme::RsaPss(hash),
+                }
+            }
+            _ => return None,
+        };
+        Some(Algorithm { digest, scheme })
+    }
+
+    /// Returns the length that the data must have, for the algorithms that sign a hash
+    /// calculated by the application and need to know its digest. Returns `None` if
+    /// the data can have any length.
+    pub fn input_len(&self) -> Option<usize> {
+        match (self.digest, self.scheme) {
+            (Digest::None, Scheme::RsaPss(hash)) => Some(hash.output_len()),
+            _ => None,
+        }
+    }
+}
+
+impl Digest {
+    /// Parses the name of a digest, e.g. "SHA256". Returns `None` if it is not supported.
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "NONE" => Some(Digest::None),
+            "SHA1" => Some(Digest::Sha1),
+            "SHA224" => Some(Digest::Sha224),
+            "SHA256" => Some(Digest::Sha256),
+            "SHA384" => Some(Digest::Sha384),
+            "SHA512" => Some(Digest::Sha512),
+            _ => None,
+        }
+    }
+
+    /// Returns the length of the hashes created by this digest, 0 for `Digest::None`.
+    pub fn output_len(&self) -> usize {
+        match self {
+            Digest::None => 0,
+            Digest::Sha1 => 20,
+            Digest::Sha224 => 28,
+            Digest::Sha256 => 32,
+            Digest::Sha384 => 48,
+            Digest::Sha512 => 64,
+        }
+    }
+
+    /// Hashes the data with this digest. `Digest::None` returns the data as is.
+    pub fn apply(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Digest::None => data.to_vec(),
+            Digest::Sha1 => Sha1::digest(data).to_vec(),
+            Digest::Sha224 => Sha224::digest(data).to_vec(),
+            Digest::Sha256 => Sha256::digest(data).to_vec(),
+            Digest::Sha384 => Sha384::digest(data).to_vec(),
+            Digest::Sha512 => Sha512::digest(data).to_vec(),
+        }
+    }
+
+    /// Returns the PKCS#1 v1.5 padding scheme that uses this digest.
+    /// `Digest::None` pads the data without a DigestInfo prefix, like
+    /// "NONEwithRSA" does.
+    pub fn pkcs1v15(&self) -> Pkcs1v15Sign {
+        match self {
+            Digest::None => Pkcs1v15Sign::new_unprefixed(),
+            Digest::Sha1 => Pkcs1v15Sign::new::<Sha1>(),
+            Digest::Sha224 => Pkcs1v15Sign::new::<Sha224>(),
+            Digest::Sha256 => Pkcs1v15Sign::new::<Sha256>(),
+            Digest::Sha384 => Pkcs1v15Sign::new::<Sha384>(),
+            Digest::Sha512 => Pkcs1v15Sign::new::<Sha512>(),
+        }
+    }
+
+    /// Returns the PSS padding scheme that uses this digest, both for hashing
+    /// and for MGF1. The salt is as long as the digest. PSS cannot be used
+    /// without a digest, so `Digest::None` returns `None`.
+    pub fn pss(&self) -> Option<Pss> {
+        match self {
+            Digest::None => None,
+            Digest::Sha1 => Some(Pss::new::<Sha1>()),
+            Digest::Sha224 => Some(Pss::new::<Sha224>()),
+            Digest::Sha256 => Some(Pss::new::<Sha256>()),
+            Digest::Sha384 => Some(Pss::new::<Sha384>()),
+            Digest::Sha512 => Some(Pss::new::<Sha512>()),
+        }
+    }
+}
+
+/// Prepends zeroes to a hash that is shorter than the field length of an EC curve.
+/// ECDSA treats the hash as an integer so this does not change its value, but
+/// the ECDSA implementation rejects hashes shorter than half of the field length.
+pub fn ec_prehash(hash: Vec<u8>, field_length: usize) -> Vec<u8> {
+    if hash.len() >= field_length {
+        return hash;
+    }
+    let mut padded = vec![0; field_length - hash.len()];
+    padded.extend(hash);
+    padded
+}
@@ -0,0 +1,48 @@
+//! Provides the keystore backends. A backend holds the private keys and
+//! performs the cryptographic operations with them. The rest of the library
+//! must not reach a keystore except through the backend returned by `get`.
+
+mod software;
+mod termux;
+
+use crate::bridge::Cancellation;
+use crate::config;
+use crate::error::Error;
+
+/// A keystore that can list its keys and sign using them.
+/// New keystore commands should be added to this trait so that every
+/// backend provides them.
+pub trait Backend: Send + Sync {
+    /// Lists all the keys. Returns a string that contains a JSON array in the
+    /// format that termux-api uses, so that it can be parsed by `key::json_to_list`.
+    fn list_keys(&self) -> Result<String, Error>;
+
+    /// Returns false if the backend cannot sign using the given algorithm, which is
+    /// in the same format as for `sign`. This is checked before starting to sign, so
+    /// that the application learns it as early as possible.
+    fn can_sign(&self, algorithm: &str) -> bool;
+
+    /// Signs the data using the key with the given alias. Algorithm parameter
+    /// must be in the format that keystore expects (e.g. "SHA512withRSA").
+    /// Returns the signature in the format keystore returns it, which means
+    /// ECDSA signatures are encoded in ASN.1. Backends that may block for a long
+    /// time should stop once the cancellation is triggered.
+    fn sign(
+        &self,
+        alias: &str,
+        algorithm: &str,
+        data: &[u8],
+        cancellation: &Cancellation,
+    ) -> Result<Vec<u8>, Error>;
+}
+
+/// Returns the backend that is selected by the configuration. The selection may
+/// change whenever the configuration is loaded again by `C_Initialize`.
+pub fn get() -> Result<Box<dyn Backend>, Error> {
+    match &config::get()?.backend {
+        config::Backend::Termux => Ok(Box::new(termux::Termux)),
+        config::Backend::Software { directory } => {
+            Ok(Box::new(software::Software::new(directory.clone())))
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! A software keystore that keeps its private keys in a directory as PKCS#8
+//! PEM files, one key per file. The file name without the `.pem` extension is
+//! used as the alias of the key.
+//! This backend does not protect the keys in any way. It exists so that the
+//! library can be developed and tested outside of an Android device.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use p256::pkcs8::DecodePrivateKey;
+use rsa::rand_core::OsRng;
+use rsa::signature::hazmat::PrehashSigner;
+use rsa::traits::PublicKeyParts;
+use rsa::RsaPrivateKey;
+use serde_json::json;
+
+use super::Backend;
+use crate::algorithm::{ec_prehash, Algorithm, Scheme};
+use crate::bridge::Cancellation;
+use crate::error::Error;
+
+/// A backend that stores the keys in the given directory.
+pub struct Software {
+    directory: PathBuf,
+}
+
+/// A private key that is read from the disk.
+enum PrivateKey {
+    Rsa(RsaPrivateKey),
+    P256(p256::ecdsa::SigningKey),
+    P384(p384::ecdsa::SigningKey),
+    P521(p521::ecdsa::SigningKey),
+}
+
+impl Software {
+    pub fn new(directory: PathBuf) -> Self {
+        Software { directory }
+    }
+
+    /// Reads all the keys in the directory. Files that are not PEM files are
+    /// skipped, and so are the keys that cannot be parsed.
+    fn keys(&self) -> Result<Vec<(String, PrivateKey)>, Error> {
+        let mut keys = Vec::new();
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            if path.extension() != Some(OsStr::new("pem")) {
+                continue;
+            }
+            let alias = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(alias) => String::from(alias),
+                None => continue,
+            };
+            if let Some(key) = PrivateKey::from_pem(&fs::read_to_string(&path)?) {
+                keys.push((alias, key));
+            }
+        }
+        keys.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(keys)
+    }
+
+    /// Reads the key with the given alias.
+    fn key(&self, alias: &str) -> Result<PrivateKey, Error> {
+        let path = self.directory.join(format!("{}.pem", alias));
+        let pem = fs::read_to_string(path).map_err(|error| match error.kind() {
+            ErrorKind::NotFound => Error::KeyNotFound(String::from(alias)),
+            _ => Error::Io(error),
+        })?;
+        PrivateKey::from_pem(&pem)
+            .ok_or_else(|| Error::Backend(format!("unsupported key: {}", alias)))
+    }
+}
+
+impl Backend for Software {
+    fn list_keys(&self) -> Result<String, Error> {
+        let keys: Vec<_> = self
+            .keys()?
+            .iter()
+            .map(|(alias, key)| key.to_json(alias))
+            .collect();
+        serde_json::to_string(&keys).map_err(|error| Error::Backend(error.to_string()))
+    }
+
+    fn can_sign(&self, algorithm: &str) -> bool {
+        Algorithm::from_name(algorithm).is_some()
+    }
+
+    fn sign(
+        &self,
+        alias: &str,
+        algorithm: &str,
+        data: &[u8],
+        _cancellation: &Cancellation,
+    ) -> Result<Vec<u8>, Error> {
+        // Signing in software never blocks, so there is nothing to cancel.
+        self.key(alias)?.sign(algorithm, data)
+    }
+}
+
+impl PrivateKey {
+    /// Parses a PKCS#8 PEM document. Returns `None` if the document does not
+    /// contain an RSA key or an EC key on one of the supported curves.
+    fn from_pem(pem: &str) -> Option<Self> {
+        if let Ok(key) = RsaPrivateKey::from_pkcs8_pem(pem) {
+            return Some(PrivateKey::Rsa(key));
+        }
+        if let Ok(key) = p256::SecretKey::from_pkcs8_pem(pem) {
+            return Some(PrivateKey::P256(key.into()));
+        }
+        if let Ok(key) = p384::SecretKey::from_pkcs8_pem(pem) {
+            return Some(PrivateKey::P384(key.into()));
+        }
+        if let Ok(key) = p521::SecretKey::from_pkcs8_pem(pem) {
+            let key = p521::ecdsa::SigningKey::from_bytes(&key.to_bytes()).ok()?;
+            return Some(PrivateKey::P521(key));
+        }
+        None
+    }
+
+    /// Describes the public part of this key the same way termux-api does.
+    fn to_json(&self, alias: &str) -> serde_json::Value {
+        let (size, point) = match self {
+            PrivateKey::Rsa(key) => {
+                return json!({
+                    "alias": alias,
+                    "algorithm": "RSA",
+                    "size": key.n().bits(),
+                    "modulus": hex::encode(key.n().to_bytes_be()),
+                    "exponent": hex::encode(key.e().to_bytes_be()),
+                });
+            }
+            PrivateKey::P256(key) => (256, key.verifying_key().to_encoded_point(false).to_bytes()),
+            PrivateKey::P384(key) => (384, key.verifying_key().to_encoded_point(false).to_bytes()),
+            PrivateKey::P521(key) => {
+                let key = p521::ecdsa::VerifyingKey::from(key);
+                (521, key.to_encoded_point(false).to_bytes())
+            }
+        };
+        // The point is uncompressed, so it is made of a tag byte followed by x and y.
+        let (x, y) = point[1..].split_at(point.len() / 2);
+        json!({
+            "alias": alias,
+            "algorithm": "EC",
+            "size": size,
+            "x": hex::encode(x),
+            "y": hex::encode(y),
+        })
+    }
+
+    /// Signs the data with this key, producing the same output keystore would.
+    fn sign(&self, algorithm: &str, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let unsupported = || Error::UnsupportedAlgorithm(String::from(algorithm));
+        let failed = |error: &dyn std::error::Error| Error::Backend(error.to_string());
+        let algorithm = Algorithm::from_name(algorithm).ok_or_else(unsupported)?;
+        let hash = algorithm.digest.apply(data);
+
+        match (self, algorithm.scheme) {
+            (PrivateKey::Rsa(key), Scheme::RsaPkcs1) => key
+                .sign(algorithm.digest.pkcs1v15(), &hash)
+                .map_err(|error| failed(&error)),
+            (PrivateKey::Rsa(key), Scheme::RsaPss(digest)) => {
+                let padding = digest.pss().ok_or_else(unsupported)?;
+                key.sign_with_rng(&mut OsRng, padding, &hash)
+                    .map_err(|error| failed(&error))
+            }
+            (PrivateKey::P256(key), Scheme::Ecdsa) => {
+                let signature: p256::ecdsa::Signature = key
+                    .sign_prehash(&ec_prehash(hash, 32))
+                    .map_err(|error| failed(&error))?;
+                Ok(signature.to_der().as_bytes().to_vec())
+            }
+            (PrivateKey::P384(key), Scheme::Ecdsa) => {
+                let signature: p384::ecdsa::Signature = key
+                    .sign_prehash(&ec_prehash(hash, 48))
+                    .map_err(|error| failed(&error))?;
+                Ok(signature.to_der().as_bytes().to_vec())
+            }
+            (PrivateKey::P521(key), Scheme::Ecdsa) => {
+                let signature: p521::ecdsa::Signature = key
+                    .sign_prehash(&ec_prehash(hash, 66))
+                    .map_err(|error| failed(&error))?;
+                Ok(signature.to_der().as_bytes().to_vec())
+            }
+            _ => Err(unsupported()),
+        }
+    }
+}
@@ -0,0 +1,31 @@
+//! The default backend, which uses the Android keystore through termux-api.
+
+use super::Backend;
+use crate::algorithm::Algorithm;
+use crate::bridge::{self, Cancellation};
+use crate::error::Error;
+
+/// Represents the Android keystore, reached through the termux-api bridge.
+pub struct Termux;
+
+impl Backend for Termux {
+    fn list_keys(&self) -> Result<String, Error> {
+        bridge::list_keys()
+    }
+
+    fn can_sign(&self, algorithm: &str) -> bool {
+        // Keystore can only use PSS padding if it calculates the digest itself, there is
+        // no keystore algorithm to sign a hash calculated by the application.
+        Algorithm::from_name(algorithm).is_some_and(|algorithm| algorithm.input_len().is_none())
+    }
+
+    fn sign(
+        &self,
+        alias: &str,
+        algorithm: &str,
+        data: &[u8],
+        cancellation: &Cancellation,
+    ) -> Result<Vec<u8>, Error> {
+        bridge::sign(alias, algorithm, data, cancellation)
+    }
+}
@@ -0,0 +1,90 @@
+//! Limits how long a call to termux-api may block, so that a dropped broadcast or a
+//! killed Termux:API app cannot hang the application forever.
+
+use std::convert::TryFrom;
+use std::os::raw::c_int;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use nix::errno::Errno;
+use nix::poll::{self, PollFd, PollFlags};
+
+use crate::error::Error;
+
+/// How often a blocked call wakes up to check whether it has been canceled.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Lets a call to termux-api be canceled from another thread.
+#[derive(Default)]
+pub struct Cancellation {
+    canceled: AtomicBool,
+}
+
+/// The point in time after which a call is abandoned, along with the cancellation that
+/// can abandon it earlier.
+pub struct Deadline<'a> {
+    expires: Option<Instant>,
+    cancellation: Option<&'a Cancellation>,
+}
+
+impl Cancellation {
+    /// Cancels the call in progress. The call notices it within `POLL_INTERVAL`.
+    pub fn cancel(&self) {
+        self.canceled.store(true, Ordering::SeqCst);
+    }
+
+    /// Clears an earlier cancellation, so that it does not affect the next call.
+    pub fn reset(&self) {
+        self.canceled.store(false, Ordering::SeqCst);
+    }
+
+    fn is_canceled(&self) -> bool {
+        self.canceled.load(Ordering::SeqCst)
+    }
+}
+
+impl<'a> Deadline<'a> {
+    /// Creates a deadline that expires after the given timeout, or never if there is none.
+    pub fn new(timeout: Option<Duration>, cancellation: Option<&'a Cancellation>) -> Self {
+        Deadline {
+            expires: timeout.map(|timeout| Instant::now() + timeout),
+            cancellation,
+        }
+    }
+
+    /// Fails with `Canceled` if the call has been canceled, and with `Timeout` if the
+    /// deadline has expired. Otherwise returns how long the caller may block before
+    /// checking again.
+    pub fn check(&self) -> Result<Duration, Error> {
+        if self.cancellation.is_some_and(Cancellation::is_canceled) {
+            return Err(Error::Canceled);
+        }
+        match self.expires {
+            Some(expires) => {
+                let remaining = expires
+                    .checked_duration_since(Instant::now())
+                    .filter(|remaining| !remaining.is_zero())
+                    .ok_or(Error::Timeout)?;
+                Ok(remaining.min(POLL_INTERVAL))
+            }
+            None => Ok(POLL_INTERVAL),
+        }
+    }
+
+    /// Blocks until the file descriptor is ready for the given events, or until the
+    /// deadline expires. Errors and hang-ups count as ready, so that the following
+    /// read or write can report them.
+    pub fn wait(&self, fd: RawFd, events: PollFlags) -> Result<(), Error> {
+        loop {
+            let interval = self.check()?;
+            let timeout = c_int::try_from(interval.as_millis()).unwrap_or(c_int::MAX);
+            let mut fds = [PollFd::new(fd, events)];
+            match poll::poll(&mut fds, timeout) {
+                Ok(0) | Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                Ok(_) => return Ok(()),
+                Err(error) => return Err(error.into()),
+            }
+        }
+    }
+}
@@ -1,70 +1,79 @@
 //! Provides the bridge to the termux-api. All communication with the
 //! termux-api must go through this module.
 
+mod deadline;
+mod request;
+mod response;
 mod socket;
-
-use std::error::Error;
-use std::io::{Read, Write};
-use std::process::{Command, Stdio};
+mod transport;
 
 use base64;
 
+use crate::config;
+use crate::error::Error;
+use deadline::Deadline;
+use request::Request;
+
+pub use deadline::Cancellation;
+
 /// Send a request to `termux-api` to list all the keys.
-/// Returns a string that contains a JSON object.
-pub fn list_keys() -> Result<String, Box<dyn Error>> {
-    Ok(communicate(&["list", "--ez", "detailed", "true"], &[0; 0])?)
+/// Returns a string that contains a JSON array.
+pub fn list_keys() -> Result<String, Error> {
+    let output = Request::new("Keystore")
+        .string("command", "list")
+        .bool("detailed", true)
+        .send(&[0; 0], None)?;
+    // Anything other than an array of keys may be an error reported by termux-api.
+    if !output.trim_start().starts_with('[') {
+        if let Some(error) = response::to_error(&output, None) {
+            return Err(error);
+        }
+    }
+    Ok(output)
 }
 
 /// Send some data to `termux-api` to be signed.
 /// Algorithm parameter must be in the format that keystore expects
 /// (e.g. "SHA512withRSA"). See the full list at the
 /// Java documentation for [Signature algorithms].
-/// Returns the signature of the data provided.
+/// Returns the signature of the data provided. Fails with `Canceled` if
+/// the cancellation is triggered before termux-api responds, and with the
+/// matching error if keystore refuses to sign, e.g. `UserNotAuthenticated`.
 ///
 /// [Signature algorithms]:
 /// https://docs.oracle.com/javase/8/docs/technotes/guides/security/StandardNames.html#Signature
-pub fn sign(alias: &str, algorithm: &str, data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
-    let args = ["sign", "-e", "alias", alias, "-e", "algorithm", algorithm];
-    let output = communicate(&args, data)?;
-    return Ok(base64::decode(output)?);
-}
-
-/// Performs a generic call to `termux-api`, providing `args` to its receiver.
-/// Sets up proper sockets so that the `input` is provided to `termux-api` and
-/// its output is returned from this function.
-fn communicate(args: &[&str], input: &[u8]) -> Result<String, Box<dyn Error>> {
-    let mut input_socket = socket::Socket::new()?;
-    let mut output_socket = socket::Socket::new()?;
-
-    // This executable does not use stdin/stdout itself.
-    let mut command = Command::new("/data/data/com.termux/files/usr/bin/am")
-        .arg("broadcast")
-        .args(&["--user", "0"])
-        .args(&["-n", "com.termux.api/.TermuxApiReceiver"])
-        .args(&["--es", "socket_input", &output_socket.address()])
-        .args(&["--es", "socket_output", &input_socket.address()])
-        .args(&["--es", "api_method", "Keystore", "-e", "command"])
-        .args(args)
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn()?;
-
-    input_socket.accept()?;
-
-    // Do not accept on the output socket if there is nothing to send.
-    // This is important as it will hang if termux-api is not expecting to receive any input.
-    if input.len() > 0 {
-        output_socket.accept()?;
-        output_socket.write(input)?;
-        output_socket.close()?;
+pub fn sign(
+    alias: &str,
+    algorithm: &str,
+    data: &[u8],
+    cancellation: &Cancellation,
+) -> Result<Vec<u8>, Error> {
+    let output = Request::new("Keystore")
+        .string("command", "sign")
+        .string("alias", alias)
+        .string("algorithm", algorithm)
+        .send(data, Some(cancellation))?;
+    let signature = base64::decode(&output).map_err(|error| {
+        response::to_error(&output, Some(alias))
+            .unwrap_or_else(|| Error::InvalidResponse(error.to_string()))
+    })?;
+    // termux-api does not respond with anything if it cannot sign the data.
+    if signature.is_empty() {
+        return Err(Error::InvalidResponse(String::from("empty signature")));
     }
+    Ok(signature)
+}
 
-    let mut output = String::new();
-    input_socket.read_to_string(&mut output)?;
-    input_socket.close()?;
-
-    // We need to reap our children otherwise they will stay as zombies.
-    command.wait()?;
-    Ok(output)
+/// Performs a generic call to `termux-api` through the configured transport, providing
+/// `input` to it and returning its output. Fails with `Timeout` if termux-api does not
+/// respond in time.
+fn communicate(
+    request: &Request,
+    input: &[u8],
+    cancellation: Option<&Cancellation>,
+) -> Result<String, Error> {
+    let config = config::get()?;
+    let deadline = Deadline::new(config.timeout, cancellation);
+    let output = transport::get(&config).send(request, input, &deadline)?;
+    String::from_utf8(output).map_err(|error| Error::InvalidResponse(error.to_string()))
 }
@@ -0,0 +1,116 @@
+//! Provides a builder for the calls to termux-api. termux-api exposes each of its
+//! features (Keystore, Fingerprint, Dialog...) as an `api_method`, which takes its
+//! arguments as intent extras.
+
+use super::{communicate, Cancellation};
+use crate::error::Error;
+
+/// A call to one of the methods of termux-api, along with its arguments.
+pub struct Request {
+    method: String,
+    extras: Vec<String>,
+}
+
+impl Request {
+    /// Creates a call to the given termux-api method, e.g. "Keystore", without any
+    /// arguments.
+    pub fn new(method: &str) -> Self {
+        Request {
+            method: String::from(method),
+            extras: Vec::new(),
+        }
+    }
+
+    /// Adds a string argument.
+    pub fn string(&mut self, name: &str, value: &str) -> &mut Self {
+        self.extra("--es", name, String::from(value))
+    }
+
+    /// Adds an integer argument.
+    #[allow(dead_code)] // Not needed by Keystore yet, but by other methods such as Dialog.
+    pub fn int(&mut self, name: &str, value: i32) -> &mut Self {
+        self.extra("--ei", name, value.to_string())
+    }
+
+    /// Adds a boolean argument.
+    pub fn bool(&mut self, name: &str, value: bool) -> &mut Self {
+        self.extra("--ez", name, value.to_string())
+    }
+
+    /// Adds a string array argument.
+    #[allow(dead_code)] // Not needed by Keystore yet, but by other methods such as Dialog.
+    pub fn strings(&mut self, name: &str, values: &[&str]) -> &mut Self {
+        // `am` separates the values with commas, so the commas in them are escaped.
+        let values: Vec<String> = values.iter().map(|v| v.replace(',', "\\,")).collect();
+        self.extra("--esa", name, values.join(","))
+    }
+
+    /// Sends this request to termux-api, providing it with `input`, and returns its
+    /// output. Fails with `Canceled` if the cancellation is triggered meanwhile.
+    pub fn send(&self, input: &[u8], cancellation: Option<&Cancellation>) -> Result<String, Error> {
+        communicate(self, input, cancellation)
+    }
+
+    /// Returns the name of the termux-api method.
+    pub(super) fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// Returns the arguments of the method, in the form `am` takes the intent extras.
+    pub(super) fn extras(&self) -> impl Iterator<Item = &str> {
+        self.extras.iter().map(String::as_str)
+    }
+
+    fn extra(&mut self, kind: &str, name: &str, value: String) -> &mut Self {
+        self.extras.push(String::from(kind));
+        self.extras.push(String::from(name));
+        self.extras.push(value);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn method_without_extras() {
+        let request = Request::new("Toast");
+        assert_eq!(request.method(), "Toast");
+        assert_eq!(request.extras().count(), 0);
+    }
+
+    #[test]
+    fn extras_in_order() {
+        let mut request = Request::new("Dialog");
+        request
+            .string("title", "Sign?")
+            .int("limit", -3)
+            .bool("multiple", false)
+            .strings("values", &["yes", "no"]);
+        let extras: Vec<&str> = request.extras().collect();
+        assert_eq!(
+            extras,
+            [
+                "--es", "title", "Sign?", "--ei", "limit", "-3", "--ez", "multiple", "false",
+                "--esa", "values", "yes,no",
+            ]
+        );
+    }
+
+    #[test]
+    fn commas_in_string_arrays_are_escaped() {
+        let mut request = Request::new("Dialog");
+        request.strings("values", &["a,b", "c", ""]);
+        let extras: Vec<&str> = request.extras().collect();
+        assert_eq!(extras, ["--esa", "values", "a\\,b,c,"]);
+    }
+
+    #[test]
+    fn commas_in_strings_are_kept() {
+        let mut request = Request::new("Keystore");
+        request.string("alias", "a,b");
+        let extras: Vec<&str> = request.extras().collect();
+        assert_eq!(extras, ["--es", "alias", "a,b"]);
+    }
+}
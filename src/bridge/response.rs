@@ -0,0 +1,125 @@
+//! Recognizes the errors that termux-api responds with instead of the expected output.
+//! They are either a JSON object with an `error` field, or the text of the Java
+//! exception that was thrown, e.g.
+//! `android.security.keystore.UserNotAuthenticatedException: User not authenticated`.
+
+use serde_json::Value;
+
+use crate::error::Error;
+
+/// Converts a response that could not be parsed to the error that it describes. `alias`
+/// is the key that the request was about, if any. Returns `None` if the response is not
+/// a recognized error either.
+pub fn to_error(response: &str, alias: Option<&str>) -> Option<Error> {
+    let response = response.trim();
+    let message = match serde_json::from_str::<Value>(response) {
+        Ok(Value::Object(object)) => match object.get("error")? {
+            Value::String(error) => error.clone(),
+            error => error.to_string(),
+        },
+        _ => response.lines().next()?.to_owned(),
+    };
+    match exception(&message) {
+        Some(exception) => Some(classify(exception, &message, alias)),
+        // A JSON error without an exception is still an error, plain text is not.
+        None if response.starts_with('{') => Some(Error::Backend(message)),
+        None => None,
+    }
+}
+
+/// Returns the simple name of the first Java exception mentioned in the message.
+fn exception(message: &str) -> Option<&str> {
+    message
+        .split(|c: char| !(c.is_alphanumeric() || c == '.' || c == '_' || c == '$'))
+        .filter_map(|word| word.rsplit(['.', '$']).next())
+        .find(|name| name.ends_with("Exception"))
+}
+
+/// Converts a Java exception thrown by Android keystore to the matching error.
+fn classify(exception: &str, message: &str, alias: Option<&str>) -> Error {
+    let key = || alias.unwrap_or(message).to_owned();
+    match exception {
+        // The key requires the user to have authenticated recently, e.g. by unlocking the
+        // device, and the validity period has passed.
+        "UserNotAuthenticatedException" => Error::UserNotAuthenticated,
+        // The lock screen has been disabled or new biometrics have been enrolled.
+        "KeyPermanentlyInvalidatedException" => Error::KeyInvalidated(key()),
+        "UnrecoverableKeyException" | "UnrecoverableEntryException" => Error::KeyNotFound(key()),
+        "NoSuchAlgorithmException" | "InvalidAlgorithmParameterException" => {
+            Error::UnsupportedAlgorithm(message.to_owned())
+        }
+        // Keystore throws this when the key is not authorized for the digest or the
+        // padding of the algorithm, which is decided when the key is generated.
+        "InvalidKeyException" => Error::OperationNotPermitted(key()),
+        _ => Error::Backend(message.to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_error() {
+        let response = r#"{"error": "android.security.keystore.UserNotAuthenticatedException"}"#;
+        assert!(matches!(
+            to_error(response, None),
+            Some(Error::UserNotAuthenticated)
+        ));
+    }
+
+    #[test]
+    fn json_error_without_exception() {
+        match to_error(r#"{"error": "key generation failed"}"#, None) {
+            Some(Error::Backend(message)) => assert_eq!(message, "key generation failed"),
+            _ => panic!("expected Backend"),
+        }
+    }
+
+    #[test]
+    fn json_without_error() {
+        assert!(to_error(r#"{"alias": "key"}"#, None).is_none());
+    }
+
+    #[test]
+    fn user_not_authenticated() {
+        let response = "android.security.keystore.UserNotAuthenticatedException: \
+                        User not authenticated\n\tat android.security.Foo.bar(Foo.java:1)\n";
+        assert!(matches!(
+            to_error(response, Some("key")),
+            Some(Error::UserNotAuthenticated)
+        ));
+    }
+
+    #[test]
+    fn key_permanently_invalidated() {
+        let response = "android.security.keystore.KeyPermanentlyInvalidatedException: \
+                        Key permanently invalidated";
+        match to_error(response, Some("key")) {
+            Some(Error::KeyInvalidated(alias)) => assert_eq!(alias, "key"),
+            _ => panic!("expected KeyInvalidated"),
+        }
+    }
+
+    #[test]
+    fn nested_exception_class() {
+        let response = "java.security.KeyStore$UnrecoverableEntryException: no entry";
+        match to_error(response, None) {
+            Some(Error::KeyNotFound(message)) => assert_eq!(message, response),
+            _ => panic!("expected KeyNotFound"),
+        }
+    }
+
+    #[test]
+    fn unknown_exception() {
+        let response = "java.lang.IllegalStateException: something else";
+        assert!(matches!(to_error(response, None), Some(Error::Backend(_))));
+    }
+
+    #[test]
+    fn unrelated_text() {
+        assert!(to_error("not a signature", None).is_none());
+        assert!(to_error("", None).is_none());
+        assert!(to_error("[]", None).is_none());
+    }
+}
@@ -1,25 +1,45 @@
-//! Provides a struct which can be used to create and use Unix abstract sockets.
+//! Provides a struct which can be used to create and use Unix abstract sockets, and
+//! the connected streams they accept. The sockets are non-blocking, every call waits
+//! for them through a `Deadline`.
+//! Abstract sockets have no file permissions, so anyone who knows the address can
+//! connect to them. Therefore only the connections from the expected user are accepted.
 
-use std::error::Error;
-use std::io::{self, ErrorKind, Read, Write};
+use std::io::{self, ErrorKind};
+use std::mem;
+use std::os::unix::io::{FromRawFd, OwnedFd};
+use std::process::Stdio;
 
-use nix::sys::socket::{self, AddressFamily, SockAddr, SockFlag, SockType, UnixAddr};
-use nix::unistd;
+use nix::errno::Errno;
+use nix::fcntl::{self, FcntlArg, OFlag};
+use nix::libc;
+use nix::poll::PollFlags;
+use nix::sys::socket::{self, sockopt, AddressFamily, SockAddr, SockFlag, SockType, UnixAddr};
+use nix::unistd::{self, Uid};
 
 use uuid::adapter::Hyphenated;
 use uuid::Uuid;
 
+use super::deadline::Deadline;
+use crate::error::Error;
+
 /// Represents an abstract Unix socket.
 pub struct Socket {
     address: SockAddr,
     server_socket: i32,
-    client_socket: Option<i32>,
+    client: Option<Stream>,
+    peer_uid: Uid,
+}
+
+/// A connected stream socket, either accepted by a `Socket` or created by `Stream::pair`.
+pub struct Stream {
+    fd: i32,
 }
 
 impl Socket {
     /// Creates a new abstract Unix socket with a randomly generated address,
-    /// binds to it and starts listening on this address.
-    pub fn new() -> Result<Self, Box<dyn Error>> {
+    /// binds to it and starts listening on this address. Only the connections
+    /// from processes running as `peer_uid` are accepted.
+    pub fn new(peer_uid: Uid) -> Result<Self, Error> {
         let mut buf = [0u8; Hyphenated::LENGTH];
         Uuid::new_v4().to_hyphenated().encode_lower(&mut buf);
         let address = UnixAddr::new_abstract(&buf)?;
@@ -27,33 +47,62 @@ impl Socket {
         let server_socket = socket::socket(
             AddressFamily::Unix,
             SockType::Stream,
-            SockFlag::SOCK_CLOEXEC,
+            SockFlag::SOCK_CLOEXEC | SockFlag::SOCK_NONBLOCK,
             None,
         )?;
-        socket::bind(server_socket, &address)?;
-        socket::listen(server_socket, 1)?;
-        Ok(Socket {
+        // The socket is closed when this is dropped, even if binding fails.
+        let socket = Socket {
             address,
             server_socket,
-            client_socket: None,
-        })
+            client: None,
+            peer_uid,
+        };
+        socket::bind(socket.server_socket, &socket.address)?;
+        socket::listen(socket.server_socket, 1)?;
+        Ok(socket)
     }
 
     /// Accept a connection through this socket. This will block until
     /// the other side of the socket connects to this socket, if not
-    /// connected already.
-    pub fn accept(&mut self) -> Result<(), Box<dyn Error>> {
-        let client_socket = socket::accept(self.server_socket)?;
-        self.client_socket = Some(client_socket);
-        Ok(())
+    /// connected already, or until the deadline expires. Connections
+    /// from other users are closed, and the socket keeps waiting.
+    pub fn accept(&mut self, deadline: &Deadline) -> Result<(), Error> {
+        loop {
+            deadline.wait(self.server_socket, PollFlags::POLLIN)?;
+            let flags = SockFlag::SOCK_CLOEXEC | SockFlag::SOCK_NONBLOCK;
+            match socket::accept4(self.server_socket, flags) {
+                Ok(client_socket) => {
+                    let client = Stream { fd: client_socket };
+                    if self.is_trusted(&client) {
+                        self.client = Some(client);
+                        return Ok(());
+                    }
+                }
+                // The connection may have been dropped since the poll.
+                Err(nix::Error::Sys(Errno::EAGAIN)) | Err(nix::Error::Sys(Errno::EINTR)) => {}
+                Err(error) => return Err(error.into()),
+            }
+        }
+    }
+
+    /// Reads from the accepted connection until the other side closes it.
+    /// See `Stream::read_to_end`.
+    pub fn read_to_end(&mut self, limit: usize, deadline: &Deadline) -> Result<Vec<u8>, Error> {
+        self.client()?.read_to_end(limit, deadline)
+    }
+
+    /// Writes all of the data to the accepted connection. See `Stream::write_all`.
+    pub fn write_all(&mut self, buf: &[u8], deadline: &Deadline) -> Result<(), Error> {
+        self.client()?.write_all(buf, deadline)
     }
 
     /// Closes this socket. It will be possible to accept another
     /// connection after this.
-    pub fn close(&mut self) -> Result<(), Box<dyn Error>> {
-        unistd::close(self.client_socket()?)?;
-        self.client_socket = None;
-        Ok(())
+    pub fn close(&mut self) -> Result<(), Error> {
+        match self.client.take() {
+            Some(client) => client.close(),
+            None => Err(self.not_connected()),
+        }
     }
 
     /// Returns the address of this socket. It will not have a leading
@@ -64,37 +113,245 @@ impl Socket {
         address
     }
 
-    /// Returns the client socket associated with this socket.
-    /// Returns `None` if the socket has not accepted a connection yet.
-    fn client_socket(&self) -> io::Result<i32> {
-        Ok(self.client_socket.ok_or(io::Error::new(
-            ErrorKind::NotConnected,
-            self.address.to_str(),
-        ))?)
+    /// Checks whether the process at the other end of the connection runs as the
+    /// expected user. The credentials are recorded by the kernel when connecting,
+    /// so they cannot be forged.
+    fn is_trusted(&self, client: &Stream) -> bool {
+        match socket::getsockopt(client.fd, sockopt::PeerCredentials) {
+            Ok(credentials) => credentials.uid() == self.peer_uid.as_raw(),
+            Err(_) => false,
+        }
+    }
+
+    /// Returns the connection accepted by this socket.
+    /// Fails if the socket has not accepted a connection yet.
+    fn client(&mut self) -> Result<&mut Stream, Error> {
+        let not_connected = self.not_connected();
+        self.client.as_mut().ok_or(not_connected)
+    }
+
+    /// Returns the error reported when there is no accepted connection to use.
+    fn not_connected(&self) -> Error {
+        io::Error::new(ErrorKind::NotConnected, self.address.to_str()).into()
     }
 }
 
-impl Read for Socket {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        unistd::read(self.client_socket()?, buf).map_err(to_io_err)
+impl Drop for Socket {
+    fn drop(&mut self) {
+        // The accepted connection, if any, is closed when it is dropped along with this.
+        let _ = unistd::close(self.server_socket);
     }
 }
 
-impl Write for Socket {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        unistd::write(self.client_socket()?, buf).map_err(to_io_err)
+impl Stream {
+    /// Creates a pair of connected sockets. The first one is used by this process, and
+    /// the second one is given to a child process as its standard input or output.
+    pub fn pair() -> Result<(Stream, Stdio), Error> {
+        let (ours, theirs) = socket::socketpair(
+            AddressFamily::Unix,
+            SockType::Stream,
+            None,
+            SockFlag::SOCK_CLOEXEC,
+        )?;
+        let theirs = unsafe { OwnedFd::from_raw_fd(theirs) };
+        let ours = Stream { fd: ours };
+        // Only our end is non-blocking, the child process expects blocking I/O.
+        let flags = OFlag::from_bits_truncate(fcntl::fcntl(ours.fd, FcntlArg::F_GETFL)?);
+        fcntl::fcntl(ours.fd, FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK))?;
+        Ok((ours, Stdio::from(theirs)))
     }
 
-    fn flush(&mut self) -> io::Result<()> {
-        // It is not possible to implement this with Unix abstract sockets.
+    /// Reads until the other side closes the connection, which marks the end of the
+    /// message. Fails with `InvalidResponse` if the message is longer than `limit`
+    /// bytes, and with `Timeout` if it is not complete before the deadline.
+    pub fn read_to_end(&mut self, limit: usize, deadline: &Deadline) -> Result<Vec<u8>, Error> {
+        let mut message = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            let len = self.read(&mut buf, deadline)?;
+            if len == 0 {
+                return Ok(message);
+            }
+            if message.len() + len > limit {
+                let reason = format!("response is longer than {} bytes", limit);
+                return Err(Error::InvalidResponse(reason));
+            }
+            message.extend_from_slice(&buf[..len]);
+        }
+    }
+
+    /// Writes all of the data, which may take several writes if the data does not fit
+    /// in the socket buffer. Fails with `Timeout` if the other side does not read all
+    /// of it before the deadline.
+    pub fn write_all(&mut self, mut buf: &[u8], deadline: &Deadline) -> Result<(), Error> {
+        while !buf.is_empty() {
+            let len = self.write(buf, deadline)?;
+            if len == 0 {
+                return Err(io::Error::from(ErrorKind::WriteZero).into());
+            }
+            buf = &buf[len..];
+        }
+        Ok(())
+    }
+
+    /// Closes the connection, which tells the other side that the whole message has
+    /// been sent.
+    pub fn close(self) -> Result<(), Error> {
+        let fd = self.fd;
+        // The descriptor is closed here instead, so that the error can be reported.
+        mem::forget(self);
+        unistd::close(fd)?;
         Ok(())
     }
+
+    /// Reads some data, blocking until there is some or until the deadline expires.
+    /// Returns 0 at the end of the stream.
+    fn read(&mut self, buf: &mut [u8], deadline: &Deadline) -> Result<usize, Error> {
+        loop {
+            deadline.wait(self.fd, PollFlags::POLLIN)?;
+            match unistd::read(self.fd, buf) {
+                Ok(len) => return Ok(len),
+                Err(nix::Error::Sys(Errno::EAGAIN)) | Err(nix::Error::Sys(Errno::EINTR)) => {}
+                Err(error) => return Err(error.into()),
+            }
+        }
+    }
+
+    /// Writes some of the data, blocking until it can be written or until the deadline
+    /// expires. Returns how much of the data is written.
+    fn write(&mut self, buf: &[u8], deadline: &Deadline) -> Result<usize, Error> {
+        loop {
+            deadline.wait(self.fd, PollFlags::POLLOUT)?;
+            // If the other side has gone away, fail with EPIPE instead of raising SIGPIPE,
+            // which would terminate the application. nix does not provide MSG_NOSIGNAL.
+            let flags = libc::MSG_NOSIGNAL;
+            let len = unsafe { libc::send(self.fd, buf.as_ptr().cast(), buf.len(), flags) };
+            match Errno::result(len) {
+                Ok(len) => return Ok(len as usize),
+                Err(nix::Error::Sys(Errno::EAGAIN)) | Err(nix::Error::Sys(Errno::EINTR)) => {}
+                Err(error) => return Err(error.into()),
+            }
+        }
+    }
 }
 
-/// Converts a [`nix::Error`] to [`io::Error`].
-///
-/// [`nix::Error`]: https://docs.rs/nix/0.18.0/nix/enum.Error.html
-/// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
-fn to_io_err(err: nix::Error) -> io::Error {
-    io::Error::new(ErrorKind::Other, err)
+impl Drop for Stream {
+    fn drop(&mut self) {
+        // The connection may still be open if a call was abandoned midway.
+        let _ = unistd::close(self.fd);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::Shutdown;
+    use std::os::unix::io::IntoRawFd;
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::bridge::transport::MAX_RESPONSE_LEN;
+
+    /// Returns a connected stream along with its peer, which is blocking.
+    fn pair() -> (Stream, UnixStream) {
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        ours.set_nonblocking(true).unwrap();
+        let ours = Stream {
+            fd: ours.into_raw_fd(),
+        };
+        (ours, theirs)
+    }
+
+    fn deadline() -> Deadline<'static> {
+        Deadline::new(Some(Duration::from_secs(10)), None)
+    }
+
+    /// Returns some data that is easy to tell apart when it is reordered.
+    fn message(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn fragments_are_reassembled() {
+        let (mut ours, mut theirs) = pair();
+        let sent = message(10_000);
+        let expected = sent.clone();
+        let peer = thread::spawn(move || {
+            for fragment in sent.chunks(7) {
+                theirs.write_all(fragment).unwrap();
+                thread::sleep(Duration::from_micros(50));
+            }
+        });
+        let received = ours.read_to_end(MAX_RESPONSE_LEN, &deadline()).unwrap();
+        peer.join().unwrap();
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn end_of_stream_ends_message() {
+        let (mut ours, mut theirs) = pair();
+        theirs.write_all(b"message").unwrap();
+        // The peer stays open, only its side of the connection is closed.
+        theirs.shutdown(Shutdown::Write).unwrap();
+        let received = ours.read_to_end(MAX_RESPONSE_LEN, &deadline()).unwrap();
+        assert_eq!(received, b"message");
+    }
+
+    #[test]
+    fn empty_message() {
+        let (mut ours, theirs) = pair();
+        drop(theirs);
+        let received = ours.read_to_end(MAX_RESPONSE_LEN, &deadline()).unwrap();
+        assert!(received.is_empty());
+    }
+
+    #[test]
+    fn message_at_limit_is_accepted() {
+        let (mut ours, mut theirs) = pair();
+        let peer = thread::spawn(move || theirs.write_all(&message(MAX_RESPONSE_LEN)));
+        let received = ours.read_to_end(MAX_RESPONSE_LEN, &deadline()).unwrap();
+        peer.join().unwrap().unwrap();
+        assert_eq!(received.len(), MAX_RESPONSE_LEN);
+    }
+
+    #[test]
+    fn message_over_limit_is_invalid() {
+        let (mut ours, mut theirs) = pair();
+        // The peer fails to write the rest once the message is rejected.
+        let peer = thread::spawn(move || theirs.write_all(&message(MAX_RESPONSE_LEN + 1)));
+        let result = ours.read_to_end(MAX_RESPONSE_LEN, &deadline());
+        assert!(matches!(result, Err(Error::InvalidResponse(_))));
+        drop(ours);
+        let _ = peer.join().unwrap();
+    }
+
+    #[test]
+    fn large_message_is_written_whole() {
+        let (mut ours, mut theirs) = pair();
+        let sent = message(4 << 20);
+        let peer = thread::spawn(move || {
+            let mut received = Vec::new();
+            let mut buf = [0; 997];
+            loop {
+                let len = theirs.read(&mut buf).unwrap();
+                if len == 0 {
+                    return received;
+                }
+                received.extend_from_slice(&buf[..len]);
+            }
+        });
+        ours.write_all(&sent, &deadline()).unwrap();
+        ours.close().unwrap();
+        assert_eq!(peer.join().unwrap(), sent);
+    }
+
+    #[test]
+    fn silent_peer_times_out() {
+        let (mut ours, _theirs) = pair();
+        let deadline = Deadline::new(Some(Duration::from_millis(50)), None);
+        let result = ours.read_to_end(MAX_RESPONSE_LEN, &deadline);
+        assert!(matches!(result, Err(Error::Timeout)));
+    }
 }
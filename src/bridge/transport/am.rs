@@ -0,0 +1,75 @@
+//! Reaches termux-api by running `am broadcast` for each request, with the sockets that
+//! termux-api connects to set up by this library. `am` starts a Java process, so this
+//! takes a while, but it works with every version of termux-api.
+
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use nix::unistd::Uid;
+
+use super::{Process, Transport, MAX_RESPONSE_LEN};
+use crate::bridge::deadline::Deadline;
+use crate::bridge::request::Request;
+use crate::bridge::socket::Socket;
+use crate::config::Config;
+use crate::error::Error;
+
+/// Delivers the requests through `am`, as configured by `am`, `user`, `receiver` and
+/// `api_uid`.
+pub struct Am {
+    am: PathBuf,
+    user: u32,
+    receiver: String,
+    api_uid: Uid,
+}
+
+impl Am {
+    pub fn new(config: &Config) -> Self {
+        Am {
+            am: config.am.clone(),
+            user: config.user,
+            receiver: config.receiver.clone(),
+            api_uid: config.api_uid,
+        }
+    }
+}
+
+impl Transport for Am {
+    fn send(&self, request: &Request, input: &[u8], deadline: &Deadline) -> Result<Vec<u8>, Error> {
+        let mut input_socket = Socket::new(self.api_uid)?;
+        let mut output_socket = Socket::new(self.api_uid)?;
+
+        // This executable does not use stdin/stdout itself.
+        let mut broadcast = Process::spawn(
+            Command::new(&self.am)
+                .arg("broadcast")
+                .args(["--user", &self.user.to_string()])
+                .args(["-n", &self.receiver])
+                .args(["--es", "socket_input", &output_socket.address()])
+                .args(["--es", "socket_output", &input_socket.address()])
+                .args(["--es", "api_method", request.method()])
+                .args(request.extras())
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null()),
+        )?;
+
+        input_socket.accept(deadline)?;
+
+        // Do not accept on the output socket if there is nothing to send.
+        // This is important as it will hang if termux-api is not expecting to receive any input.
+        if !input.is_empty() {
+            output_socket.accept(deadline)?;
+            output_socket.write_all(input, deadline)?;
+            // Closing the connection tells termux-api that the whole input has been sent.
+            output_socket.close()?;
+        }
+
+        // Likewise termux-api closes the connection once the whole output has been sent.
+        let output = input_socket.read_to_end(MAX_RESPONSE_LEN, deadline)?;
+        input_socket.close()?;
+
+        broadcast.wait(deadline)?;
+        Ok(output)
+    }
+}
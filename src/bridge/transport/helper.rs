@@ -0,0 +1,64 @@
+//! Reaches termux-api through `libexec/termux-api`, the helper that the termux-api
+//! package provides for its scripts. The helper sets up the sockets itself, and forwards
+//! its standard input to termux-api and the output of termux-api to its standard output.
+//! Recent versions of the helper have the Termux app deliver the broadcast instead of
+//! starting `am`, which makes the requests much faster.
+//!
+//! The helper does not check who connects to its sockets, unlike the `am` transport,
+//! so any app that guesses the socket names can forge the key list or the signatures.
+//! Therefore it is only used if it is selected explicitly.
+
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use super::{Process, Transport, MAX_RESPONSE_LEN};
+use crate::bridge::deadline::Deadline;
+use crate::bridge::request::Request;
+use crate::bridge::socket::Stream;
+use crate::error::Error;
+
+/// Delivers the requests through the helper at the given path.
+pub struct Helper {
+    path: PathBuf,
+}
+
+impl Helper {
+    pub fn new(path: PathBuf) -> Self {
+        Helper { path }
+    }
+}
+
+impl Transport for Helper {
+    fn send(&self, request: &Request, input: &[u8], deadline: &Deadline) -> Result<Vec<u8>, Error> {
+        // Sockets are used instead of pipes, so that writing to a helper that has exited
+        // fails instead of raising SIGPIPE.
+        let (mut output, output_stdio) = Stream::pair()?;
+        let (input_stream, input_stdio) = if input.is_empty() {
+            (None, Stdio::null())
+        } else {
+            let (stream, stdio) = Stream::pair()?;
+            (Some(stream), stdio)
+        };
+
+        // The command is dropped right away, so that the child process holds the only
+        // copies of its ends of the sockets, and the output ends when the child closes it.
+        let mut helper = Process::spawn(
+            Command::new(&self.path)
+                .arg(request.method())
+                .args(request.extras())
+                .stdin(input_stdio)
+                .stdout(output_stdio)
+                .stderr(Stdio::null()),
+        )?;
+
+        if let Some(mut input_stream) = input_stream {
+            input_stream.write_all(input, deadline)?;
+            // The helper passes the end of the input on to termux-api.
+            input_stream.close()?;
+        }
+
+        let output = output.read_to_end(MAX_RESPONSE_LEN, deadline)?;
+        helper.wait(deadline)?;
+        Ok(output)
+    }
+}
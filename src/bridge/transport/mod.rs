@@ -0,0 +1,73 @@
+//! Provides the transports, which deliver the requests to termux-api and bring back its
+//! output. They send the same requests and return the same output, but differ in how
+//! they reach termux-api, how long that takes, and whether they check who connects to
+//! the sockets, see `helper`.
+
+mod am;
+mod helper;
+
+use std::io::ErrorKind;
+use std::process::{Child, Command};
+use std::thread;
+use std::time::Duration;
+
+use super::deadline::Deadline;
+use super::request::Request;
+use crate::config::{self, Config};
+use crate::error::Error;
+
+/// The longest response accepted from termux-api. The responses are short, the longest
+/// one is the key list, so anything longer means that something has gone wrong.
+pub(super) const MAX_RESPONSE_LEN: usize = 1 << 20;
+
+/// A way of delivering the requests to termux-api.
+pub trait Transport {
+    /// Delivers the request to termux-api, provides it with `input` and returns its
+    /// output. Nothing is sent if `input` is empty, as termux-api does not read any
+    /// input for the requests that do not expect one. Fails with `Timeout` or
+    /// `Canceled` once the deadline tells so.
+    fn send(&self, request: &Request, input: &[u8], deadline: &Deadline) -> Result<Vec<u8>, Error>;
+}
+
+/// A running `am` or termux-api helper process, which is killed if it is dropped before
+/// exiting by itself.
+struct Process(Child);
+
+/// Returns the transport selected by the configuration.
+pub fn get(config: &Config) -> Box<dyn Transport> {
+    match &config.transport {
+        config::Transport::Am => Box::new(am::Am::new(config)),
+        config::Transport::Helper(path) => Box::new(helper::Helper::new(path.clone())),
+    }
+}
+
+impl Process {
+    /// Starts the command. Fails with `NotInstalled` if its executable does not exist,
+    /// as there is no way to reach termux-api without it.
+    fn spawn(command: &mut Command) -> Result<Self, Error> {
+        let child = command.spawn().map_err(|error| match error.kind() {
+            ErrorKind::NotFound => Error::NotInstalled,
+            _ => Error::Io(error),
+        })?;
+        Ok(Process(child))
+    }
+
+    /// Waits for the process to exit, which it does once the request is delivered.
+    fn wait(&mut self, deadline: &Deadline) -> Result<(), Error> {
+        while self.0.try_wait()?.is_none() {
+            thread::sleep(deadline.check()?.min(Duration::from_millis(10)));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        // We need to reap our children otherwise they will stay as zombies. If the call
+        // was abandoned, the process may still be waiting for termux-api, so it is killed.
+        if let Ok(None) = self.0.try_wait() {
+            let _ = self.0.kill();
+        }
+        let _ = self.0.wait();
+    }
+}
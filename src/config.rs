@@ -0,0 +1,298 @@
+//! Provides the configuration of the library. The settings are read from
+//! `$PREFIX/etc/tergent/tergent.conf` for the whole system, then from
+//! `~/.config/tergent/tergent.conf` for the user, and finally from the environment
+//! variables. Each source overrides the settings of the ones before it.
+//!
+//! The files consist of `name = value` lines, like `termux.properties`. Empty lines
+//! and lines starting with `#` are ignored.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use nix::unistd::{self, Uid};
+
+use crate::error::Error;
+
+/// The settings that can be configured, along with the environment variables that
+/// override them.
+const SETTINGS: &[(&str, &str)] = &[
+    ("backend", "TERGENT_BACKEND"),
+    ("software_keystore", "TERGENT_SOFTWARE_KEYSTORE"),
+    ("transport", "TERGENT_TRANSPORT"),
+    ("am", "TERGENT_AM"),
+    ("user", "TERGENT_USER"),
+    ("receiver", "TERGENT_RECEIVER"),
+    ("timeout", "TERGENT_TIMEOUT"),
+    ("api_uid", "TERGENT_API_UID"),
+];
+
+/// The prefix of Termux, used if `$PREFIX` is not set.
+const DEFAULT_PREFIX: &str = "/data/data/com.termux/files/usr";
+
+/// The receiver of termux-api, used if `receiver` is not set.
+const DEFAULT_RECEIVER: &str = "com.termux.api/.TermuxApiReceiver";
+
+/// The timeout used if `timeout` is not set. Signing may wait for the user to
+/// authenticate, so this is generous.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Android gives each user a range of this many user ids, so the Android user of a
+/// process can be derived from its user id.
+const PER_USER_RANGE: u32 = 100_000;
+
+/// The configuration loaded most recently. `None` if it has not been loaded yet.
+static INSTANCE: Mutex<Option<Arc<Config>>> = Mutex::new(None);
+
+/// The configuration of the library.
+pub struct Config {
+    /// The keystore backend, selected by `backend`.
+    pub backend: Backend,
+    /// How termux-api is reached, selected by `transport`.
+    pub transport: Transport,
+    /// Path of the `am` executable, which is used by the `am` transport.
+    pub am: PathBuf,
+    /// The Android user that Termux runs as. Defaults to the user of this process, so
+    /// that work profiles and secondary users work without configuration.
+    pub user: u32,
+    /// The broadcast receiver of termux-api, in the `package/class` form.
+    pub receiver: String,
+    /// How long a call to termux-api may take before it is abandoned. `None` if the
+    /// calls never time out, which is set by `timeout = 0`.
+    pub timeout: Option<Duration>,
+    /// The user id of the Termux:API app. Only the connections from this user are
+    /// trusted. Defaults to the user id of this process, as Termux and its plugins share
+    /// the same user id.
+    pub api_uid: Uid,
+}
+
+/// The keystore backends that can be selected.
+pub enum Backend {
+    /// The Android keystore, reached through termux-api.
+    Termux,
+    /// The software keystore, which keeps its keys in the given directory.
+    /// The directory defaults to `~/.local/share/tergent/keys`.
+    Software { directory: PathBuf },
+}
+
+/// The ways of reaching termux-api that can be selected.
+pub enum Transport {
+    /// Running `am broadcast` for each call, which is the default.
+    Am,
+    /// Running the `libexec/termux-api` helper of the termux-api package, at the given
+    /// path, for each call. This is faster, but the helper does not check who connects
+    /// to its sockets, so any app that guesses their names can forge the responses.
+    Helper(PathBuf),
+}
+
+/// The value of a setting, along with where it was read from.
+struct Setting {
+    name: &'static str,
+    value: String,
+    /// The file and the line, or the environment variable, the value comes from.
+    source: String,
+}
+
+/// Returns the configuration, loading it if it has not been loaded yet.
+pub fn get() -> Result<Arc<Config>, Error> {
+    if let Some(config) = lock().as_ref() {
+        return Ok(Arc::clone(config));
+    }
+    load()
+}
+
+/// Reads the configuration again, so that the changes made since the last time take
+/// effect. Fails with `Config` if a setting is unknown or has an invalid value.
+pub fn load() -> Result<Arc<Config>, Error> {
+    let prefix = env::var_os("PREFIX").map_or_else(|| PathBuf::from(DEFAULT_PREFIX), PathBuf::from);
+    let mut settings = HashMap::new();
+    read_file(&prefix.join("etc/tergent/tergent.conf"), &mut settings)?;
+    if let Some(config_home) = config_home() {
+        read_file(&config_home.join("tergent/tergent.conf"), &mut settings)?;
+    }
+    for &(name, variable) in SETTINGS {
+        if let Ok(value) = env::var(variable) {
+            let source = format!("environment variable {}", variable);
+            settings.insert(name, Setting::new(name, &value, source));
+        }
+    }
+    let config = Arc::new(Config::new(&prefix, &settings)?);
+    *lock() = Some(Arc::clone(&config));
+    Ok(config)
+}
+
+impl Config {
+    /// Creates the configuration from the given settings, using the defaults for the
+    /// settings that are missing.
+    fn new(prefix: &Path, settings: &HashMap<&str, Setting>) -> Result<Self, Error> {
+        let backend = match settings.get("backend") {
+            None => Backend::Termux,
+            Some(setting) => match setting.value.as_str() {
+                "termux" => Backend::Termux,
+                "software" => Backend::Software {
+                    directory: software_keystore(settings.get("software_keystore"))?,
+                },
+                _ => return Err(setting.invalid("either termux or software")),
+            },
+        };
+
+        let am = match settings.get("am") {
+            Some(setting) if !Path::new(&setting.value).is_absolute() => {
+                return Err(setting.invalid("an absolute path"));
+            }
+            Some(setting) => PathBuf::from(&setting.value),
+            None => prefix.join("bin/am"),
+        };
+
+        let transport = transport(prefix, settings)?;
+
+        let user = match settings.get("user") {
+            Some(setting) => setting.parse("an Android user id")?,
+            None => unistd::getuid().as_raw() / PER_USER_RANGE,
+        };
+
+        let receiver = match settings.get("receiver") {
+            Some(setting) => {
+                let valid = setting
+                    .value
+                    .split_once('/')
+                    .is_some_and(|(package, class)| !package.is_empty() && !class.is_empty());
+                if !valid {
+                    return Err(setting.invalid("in the form package/class"));
+                }
+                setting.value.clone()
+            }
+            None => String::from(DEFAULT_RECEIVER),
+        };
+
+        let timeout = match settings.get("timeout") {
+            Some(setting) => match setting.parse("a number of seconds")? {
+                0 => None,
+                seconds => Some(Duration::from_secs(seconds)),
+            },
+            None => Some(DEFAULT_TIMEOUT),
+        };
+
+        let api_uid = match settings.get("api_uid") {
+            Some(setting) => Uid::from_raw(setting.parse("a user id")?),
+            None => unistd::getuid(),
+        };
+
+        Ok(Config {
+            backend,
+            transport,
+            am,
+            user,
+            receiver,
+            timeout,
+            api_uid,
+        })
+    }
+}
+
+impl Setting {
+    fn new(name: &'static str, value: &str, source: String) -> Self {
+        Setting {
+            name,
+            value: String::from(value.trim()),
+            source,
+        }
+    }
+
+    /// Parses the value, failing with an error that tells what was `expected` otherwise.
+    fn parse<T: FromStr>(&self, expected: &str) -> Result<T, Error> {
+        self.value.parse().map_err(|_| self.invalid(expected))
+    }
+
+    /// Returns an error telling that the value is invalid and what was `expected`.
+    fn invalid(&self, expected: &str) -> Error {
+        let reason = format!("{}: {} must be {}", self.source, self.name, expected);
+        Error::Config(reason)
+    }
+}
+
+/// Reads the settings in the given file, overriding the ones read before. A missing
+/// file is not an error, as all the settings are optional.
+fn read_file(path: &Path, settings: &mut HashMap<&'static str, Setting>) -> Result<(), Error> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(error) => return Err(Error::Config(format!("{}: {}", path.display(), error))),
+    };
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let source = format!("{}:{}", path.display(), number + 1);
+        let (name, value) = line
+            .split_once('=')
+            .ok_or_else(|| Error::Config(format!("{}: expected name = value", source)))?;
+        let name = SETTINGS
+            .iter()
+            .map(|&(known, _)| known)
+            .find(|&known| known == name.trim())
+            .ok_or_else(|| Error::Config(format!("{}: unknown setting {}", source, name.trim())))?;
+        settings.insert(name, Setting::new(name, value, source));
+    }
+    Ok(())
+}
+
+/// Returns the directory of the software keystore, which is either configured or
+/// `~/.local/share/tergent/keys`.
+fn software_keystore(setting: Option<&Setting>) -> Result<PathBuf, Error> {
+    match setting {
+        Some(setting) if setting.value.is_empty() => Err(setting.invalid("a directory")),
+        Some(setting) => Ok(PathBuf::from(&setting.value)),
+        None => {
+            let home = env::var_os("HOME").ok_or_else(|| {
+                Error::Config(String::from(
+                    "HOME is not set, set software_keystore instead",
+                ))
+            })?;
+            Ok(PathBuf::from(home).join(".local/share/tergent/keys"))
+        }
+    }
+}
+
+/// Returns the transport, which is `am` unless the helper is selected. The helper does
+/// not use `am`, `user`, `receiver` nor `api_uid`, so they cannot be set along with
+/// it, rather than being ignored.
+fn transport(prefix: &Path, settings: &HashMap<&str, Setting>) -> Result<Transport, Error> {
+    let setting = match settings.get("transport") {
+        Some(setting) => setting,
+        None => return Ok(Transport::Am),
+    };
+    match setting.value.as_str() {
+        "am" => Ok(Transport::Am),
+        "helper" => {
+            let ignored = ["am", "user", "receiver", "api_uid"]
+                .iter()
+                .find_map(|&name| settings.get(name));
+            match ignored {
+                Some(ignored) => Err(ignored.invalid("unset, as transport = helper ignores it")),
+                None => Ok(Transport::Helper(prefix.join("libexec/termux-api"))),
+            }
+        }
+        _ => Err(setting.invalid("either am or helper")),
+    }
+}
+
+/// Returns the directory that holds the configuration of the user.
+fn config_home() -> Option<PathBuf> {
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(config_home) => Some(PathBuf::from(config_home)),
+        None => Some(PathBuf::from(env::var_os("HOME")?).join(".config")),
+    }
+}
+
+/// Locks the configuration. The configuration is only ever replaced as a whole, so it
+/// is still consistent if the mutex is poisoned.
+fn lock() -> MutexGuard<'static, Option<Arc<Config>>> {
+    INSTANCE.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -0,0 +1,102 @@
+//! Provides the error type of the keystore operations. The errors are created by the
+//! bridge and the backends, passed through the keys and the states unchanged, and only
+//! converted to a `ReturnValue` when they reach an entry point.
+
+use std::fmt;
+use std::io;
+
+use crate::pkcs11::ReturnValue;
+
+/// Describes why a keystore operation has failed.
+#[derive(Debug)]
+pub enum Error {
+    /// termux-api is not installed, so the keystore cannot be reached.
+    NotInstalled,
+    /// Communicating with the keystore has failed, for example because of a socket error.
+    Io(io::Error),
+    /// The keystore has not responded in time.
+    Timeout,
+    /// The call has been canceled by `C_CancelFunction`.
+    Canceled,
+    /// The keystore has responded with something that could not be parsed.
+    InvalidResponse(String),
+    /// The key does not exist in the keystore, it may have been deleted.
+    KeyNotFound(String),
+    /// The key has been permanently invalidated by the keystore, for example because the
+    /// lock screen has been disabled. It cannot be used anymore.
+    KeyInvalidated(String),
+    /// The key can only be used after the user has authenticated.
+    UserNotAuthenticated,
+    /// The key is not authorized for the operation, for example because the digest of
+    /// the signature algorithm was not allowed when the key was generated.
+    OperationNotPermitted(String),
+    /// The keystore does not support the given signature algorithm.
+    UnsupportedAlgorithm(String),
+    /// The backend could not be set up, for example because it is configured wrong.
+    Config(String),
+    /// The keystore has failed for some other reason.
+    Backend(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotInstalled => write!(f, "termux-api is not installed"),
+            Error::Io(error) => write!(f, "cannot communicate with the keystore: {}", error),
+            Error::Timeout => write!(f, "the keystore has not responded in time"),
+            Error::Canceled => write!(f, "canceled"),
+            Error::InvalidResponse(reason) => write!(f, "invalid keystore response: {}", reason),
+            Error::KeyNotFound(alias) => write!(f, "key not found: {}", alias),
+            Error::KeyInvalidated(alias) => write!(f, "key permanently invalidated: {}", alias),
+            Error::UserNotAuthenticated => write!(f, "user not authenticated, unlock the device"),
+            Error::OperationNotPermitted(alias) => {
+                write!(f, "operation not permitted by the key: {}", alias)
+            }
+            Error::UnsupportedAlgorithm(algorithm) => {
+                write!(f, "unsupported algorithm: {}", algorithm)
+            }
+            Error::Config(reason) => write!(f, "invalid configuration: {}", reason),
+            Error::Backend(reason) => write!(f, "keystore failure: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<nix::Error> for Error {
+    fn from(error: nix::Error) -> Self {
+        Error::Io(io::Error::other(error))
+    }
+}
+
+impl From<Error> for ReturnValue {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::NotInstalled => ReturnValue::TokenNotPresent,
+            Error::Io(_) | Error::Timeout | Error::InvalidResponse(_) | Error::Backend(_) => {
+                ReturnValue::DeviceError
+            }
+            Error::Canceled => ReturnValue::FunctionCanceled,
+            Error::KeyNotFound(_) => ReturnValue::KeyHandleInvalid,
+            Error::KeyInvalidated(_) | Error::OperationNotPermitted(_) => {
+                ReturnValue::KeyFunctionNotPermitted
+            }
+            Error::UserNotAuthenticated => ReturnValue::UserNotLoggedIn,
+            Error::UnsupportedAlgorithm(_) => ReturnValue::MechanismInvalid,
+            Error::Config(_) => ReturnValue::GeneralError,
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Helpers for the entry points of the library. The application calling them is written
+//! in C, so the entry points must never let a panic unwind into it, which is undefined
+//! behavior. The arguments also come straight from the application, so the pointers and
+//! the lengths are validated before they are used.
+
+use std::convert::TryFrom;
+use std::os::raw::c_ulong;
+use std::panic::{self, AssertUnwindSafe};
+use std::slice;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::library;
+use crate::pkcs11::ReturnValue;
+use crate::state::{self, State};
+
+/// Runs the body of an entry point, and converts its result to the value returned to the
+/// application. A panic is caught and reported as `GeneralError`.
+pub fn guard<F>(function: F) -> c_ulong
+where
+    F: FnOnce() -> Result<(), ReturnValue>,
+{
+    // Asserting unwind safety is fine, as the only state that outlives a call is behind
+    // mutexes, and those are poisoned by a panic. `lock` reports poisoned mutexes.
+    let return_value = match panic::catch_unwind(AssertUnwindSafe(function)) {
+        Ok(Ok(())) => ReturnValue::Ok,
+        Ok(Err(return_value)) => return_value,
+        Err(_) => ReturnValue::GeneralError,
+    };
+    return_value as c_ulong
+}
+
+/// Returns the result of the functions that the library does not support. Like any
+/// other function, they fail with `CryptokiNotInitialized` before `C_Initialize`.
+pub fn not_supported() -> c_ulong {
+    guard(|| {
+        initialized()?;
+        Err(ReturnValue::FunctionNotSupported)
+    })
+}
+
+/// Fails with `CryptokiNotInitialized` unless `C_Initialize` has been called.
+pub fn initialized() -> Result<(), ReturnValue> {
+    if library::is_initialized() {
+        Ok(())
+    } else {
+        Err(ReturnValue::CryptokiNotInitialized)
+    }
+}
+
+/// Fetches the state of the given session.
+pub fn session(session: c_ulong) -> Result<Arc<Mutex<State>>, ReturnValue> {
+    state::get(session).ok_or(ReturnValue::SessionHandleInvalid)
+}
+
+/// Locks the given mutex. A poisoned mutex means that an earlier call has panicked while
+/// holding it, so the data it protects cannot be trusted anymore.
+pub fn lock<T>(mutex: &Mutex<T>) -> Result<MutexGuard<'_, T>, ReturnValue> {
+    mutex.lock().map_err(|_| ReturnValue::GeneralError)
+}
+
+/// Converts a value given by the application, failing with `ArgumentsBad` if the value
+/// does not fit, for example when a length does not fit in `usize`.
+pub fn argument<T, U: TryFrom<T>>(value: T) -> Result<U, ReturnValue> {
+    U::try_from(value).map_err(|_| ReturnValue::ArgumentsBad)
+}
+
+/// Converts a value that is reported to the application, failing with `GeneralError`
+/// if the value does not fit.
+pub fn convert<T, U: TryFrom<T>>(value: T) -> Result<U, ReturnValue> {
+    U::try_from(value).map_err(|_| ReturnValue::GeneralError)
+}
+
+/// Converts a pointer given by the application to a reference, failing with
+/// `ArgumentsBad` if it is null.
+///
+/// # Safety
+///
+/// The pointer must either be null, or point to a valid `T` that is not used elsewhere
+/// for the duration of `'a`.
+pub unsafe fn reference<'a, T>(pointer: *mut T) -> Result<&'a mut T, ReturnValue> {
+    pointer.as_mut().ok_or(ReturnValue::ArgumentsBad)
+}
+
+/// Converts a buffer given by the application to a slice, failing with `ArgumentsBad`
+/// if it is null. An empty buffer may be null.
+///
+/// # Safety
+///
+/// The pointer must either be null, or point to `len` valid consecutive `T`s that are
+/// not modified for the duration of `'a`.
+pub unsafe fn slice<'a, T>(pointer: *const T, len: c_ulong) -> Result<&'a [T], ReturnValue> {
+    let len = argument(len)?;
+    if len == 0 {
+        Ok(&[])
+    } else if pointer.is_null() {
+        Err(ReturnValue::ArgumentsBad)
+    } else {
+        Ok(slice::from_raw_parts(pointer, len))
+    }
+}
+
+/// Converts a buffer given by the application to a mutable slice, failing with
+/// `ArgumentsBad` if it is null. An empty buffer may be null.
+///
+/// # Safety
+///
+/// The pointer must either be null, or point to `len` valid consecutive `T`s that are
+/// not used elsewhere for the duration of `'a`.
+pub unsafe fn slice_mut<'a, T>(pointer: *mut T, len: c_ulong) -> Result<&'a mut [T], ReturnValue> {
+    let len = argument(len)?;
+    if len == 0 {
+        Ok(&mut [])
+    } else if pointer.is_null() {
+        Err(ReturnValue::ArgumentsBad)
+    } else {
+        Ok(slice::from_raw_parts_mut(pointer, len))
+    }
+}
@@ -5,7 +5,7 @@ use std::iter;
 
 use simple_asn1::*;
 
-use super::{EcCurve, EcKey};
+use super::{EcCurve, EcKey, RsaKey};
 
 /// For elliptic curves, 2 and 3 means compressed and 4 means uncompressed.
 /// We only support the uncompressed point representation.
@@ -14,45 +14,96 @@ const EC_UNCOMPRESSED_POINT: u8 = 4;
 impl EcCurve {
     /// Returns the required parameter length for this curve. All values
     /// (x, y, r and s) must be prepended with zeroes so that they have this length.
-    fn param_length(&self) -> usize {
+    pub fn param_length(&self) -> usize {
         match &self {
             EcCurve::P256 => 32,
             EcCurve::P384 => 48,
             EcCurve::P521 => 66,
         }
     }
+
+    /// Returns the object identifier of this curve.
+    fn oid(&self) -> OID {
+        match self {
+            EcCurve::P256 => oid!(1, 2, 840, 10045, 3, 1, 7),
+            EcCurve::P384 => oid!(1, 3, 132, 0, 34),
+            EcCurve::P521 => oid!(1, 3, 132, 0, 35),
+        }
+    }
+}
+
+impl RsaKey {
+    /// Returns the public key of this RSA key (the modulus and the exponent),
+    /// formatted as an ASN.1 RSAPublicKey structure.
+    pub fn public_key_as_asn1(&self) -> Result<Vec<u8>, ASN1EncodeErr> {
+        let modulus = BigInt::from(BigUint::from_bytes_be(&self.modulus));
+        let exponent = BigInt::from(BigUint::from_bytes_be(&self.exponent));
+        let asn = ASN1Block::Sequence(
+            0,
+            vec![
+                ASN1Block::Integer(0, modulus),
+                ASN1Block::Integer(0, exponent),
+            ],
+        );
+        to_der(&asn)
+    }
+
+    /// Returns the public key of this RSA key as an ASN.1 SubjectPublicKeyInfo structure,
+    /// which is the format X.509 certificates use.
+    pub fn public_key_info_as_asn1(&self) -> Result<Vec<u8>, ASN1EncodeErr> {
+        let algorithm = ASN1Block::Sequence(
+            0,
+            vec![
+                ASN1Block::ObjectIdentifier(0, oid!(1, 2, 840, 113549, 1, 1, 1)),
+                ASN1Block::Null(0),
+            ],
+        );
+        public_key_info(algorithm, self.public_key_as_asn1()?)
+    }
 }
 
 impl EcKey {
     /// Returns the point values (x and y), which is the public key of this EC key.
-    /// The output is formatted in ASN.1.
-    pub fn point_as_asn1(&self) -> Result<Vec<u8>, ASN1EncodeErr> {
+    /// The output is in the uncompressed point representation, without any ASN.1 wrapping.
+    pub fn point(&self) -> Vec<u8> {
         let x_prepend = &self.curve.param_length() - self.x.len();
         let y_prepend = &self.curve.param_length() - self.y.len();
 
-        let point = iter::once(EC_UNCOMPRESSED_POINT)
+        iter::once(EC_UNCOMPRESSED_POINT)
             .chain(iter::repeat(0).take(x_prepend))
             .chain(self.x.iter().copied())
             .chain(iter::repeat(0).take(y_prepend))
             .chain(self.y.iter().copied())
-            .collect();
+            .collect()
+    }
 
-        let asn = ASN1Block::OctetString(0, point);
+    /// Returns the point values (x and y), which is the public key of this EC key.
+    /// The output is formatted in ASN.1.
+    pub fn point_as_asn1(&self) -> Result<Vec<u8>, ASN1EncodeErr> {
+        let asn = ASN1Block::OctetString(0, self.point());
         to_der(&asn)
     }
 
     /// Returns the params of this EC key, which represents the curve used in this key.
     /// The output is formatted in ASN.1.
     pub fn params_as_asn1(&self) -> Result<Vec<u8>, ASN1EncodeErr> {
-        let oid = match self.curve {
-            EcCurve::P256 => oid!(1, 2, 840, 10045, 3, 1, 7),
-            EcCurve::P384 => oid!(1, 3, 132, 0, 34),
-            EcCurve::P521 => oid!(1, 3, 132, 0, 35),
-        };
-        let asn = ASN1Block::ObjectIdentifier(0, oid);
+        let asn = ASN1Block::ObjectIdentifier(0, self.curve.oid());
         to_der(&asn)
     }
 
+    /// Returns the public key of this EC key as an ASN.1 SubjectPublicKeyInfo structure,
+    /// which is the format X.509 certificates use.
+    pub fn public_key_info_as_asn1(&self) -> Result<Vec<u8>, ASN1EncodeErr> {
+        let algorithm = ASN1Block::Sequence(
+            0,
+            vec![
+                ASN1Block::ObjectIdentifier(0, oid!(1, 2, 840, 10045, 2, 1)),
+                ASN1Block::ObjectIdentifier(0, self.curve.oid()),
+            ],
+        );
+        public_key_info(algorithm, self.point())
+    }
+
     /// Parses a signature value formatted in ASN.1, and returns a vector that contains
     /// the signature values (r and s), with proper padding so that it has the expected
     /// total length (2*`param_length()`).
@@ -95,3 +146,14 @@ impl EcKey {
         )
     }
 }
+
+/// Builds a SubjectPublicKeyInfo structure from the algorithm identifier and the
+/// encoded public key.
+fn public_key_info(algorithm: ASN1Block, public_key: Vec<u8>) -> Result<Vec<u8>, ASN1EncodeErr> {
+    let bits = public_key.len() * 8;
+    let asn = ASN1Block::Sequence(
+        0,
+        vec![algorithm, ASN1Block::BitString(0, bits, public_key)],
+    );
+    to_der(&asn)
+}
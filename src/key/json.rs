@@ -5,53 +5,82 @@ use hex;
 use serde_json;
 
 use super::{EcCurve, EcKey, Key, RsaKey};
+use crate::error::Error;
 
-/// Convents a JSON string to key vector. Returns `None` if the overall
-/// structure is invalid. Skips over the keys that are unparseable.
-pub fn to_list(json: String) -> Option<Vec<Key>> {
-    let keys = serde_json::from_str::<serde_json::Value>(&json).ok()?;
-    let keys = keys.as_array()?;
+/// Convents a JSON string to key vector. Returns `InvalidResponse` if the overall
+/// structure is invalid. Skips over the kinds of keys that tergent does not support,
+/// and the keys that are unparseable, which are reported on the standard error. If
+/// none of the keys can be parsed, fails with `InvalidResponse` instead.
+pub fn to_list(json: String) -> Result<Vec<Key>, Error> {
+    let keys = serde_json::from_str::<serde_json::Value>(&json)
+        .map_err(|error| Error::InvalidResponse(error.to_string()))?;
+    let keys = keys
+        .as_array()
+        .ok_or_else(|| Error::InvalidResponse(String::from("expected an array of keys")))?;
 
-    Some(keys.iter().filter_map(parse_key).collect())
+    let mut list = Vec::new();
+    let mut invalid = Vec::new();
+    for key in keys {
+        match parse_key(key) {
+            Ok(Some(key)) => list.push(key),
+            Ok(None) => {}
+            Err(reason) => invalid.push(reason),
+        }
+    }
+    if list.is_empty() && !invalid.is_empty() {
+        return Err(Error::InvalidResponse(invalid.join(", ")));
+    }
+    for reason in invalid {
+        eprintln!("tergent: skipping key: {}", reason);
+    }
+    Ok(list)
 }
 
-/// Parse a single JSON object containing information about a key.
-fn parse_key(object: &serde_json::Value) -> Option<Key> {
-    let alias = object.get("alias")?.as_str()?;
-    let algorithm = object.get("algorithm")?.as_str()?;
-    let size = object.get("size")?.as_u64()?;
+/// Parse a single JSON object containing information about a key. Returns `None` if
+/// tergent does not support this kind of key, e.g. AES keys, and the reason if the
+/// object cannot be parsed.
+fn parse_key(object: &serde_json::Value) -> Result<Option<Key>, String> {
+    let alias = object
+        .get("alias")
+        .and_then(serde_json::Value::as_str)
+        .ok_or("key without an alias")?;
+    let field = |name: &str| {
+        object
+            .get(name)
+            .ok_or_else(|| format!("{}: missing {}", alias, name))
+    };
+    let invalid = |name: &str| format!("{}: invalid {}", alias, name);
+    let hex = |name: &str| field(name)?.as_hex().ok_or_else(|| invalid(name));
+    let algorithm = field("algorithm")?
+        .as_str()
+        .ok_or_else(|| invalid("algorithm"))?;
+    let size = field("size")?.as_u64().ok_or_else(|| invalid("size"))?;
 
     match algorithm {
         "RSA" => {
-            let modulus = object.get("modulus")?.as_hex()?;
-            let exponent = object.get("exponent")?.as_hex()?;
             let key = RsaKey {
                 label: String::from(alias),
-                modulus,
-                exponent,
+                modulus: hex("modulus")?,
+                exponent: hex("exponent")?,
             };
-            Some(Key::Rsa(key))
+            Ok(Some(Key::Rsa(key)))
         }
         "EC" => {
-            let x = object.get("x")?.as_hex()?;
-            let y = object.get("y")?.as_hex()?;
             let curve = match size {
                 256 => EcCurve::P256,
                 384 => EcCurve::P384,
                 521 => EcCurve::P521,
-                _ => {
-                    return None;
-                }
+                _ => return Ok(None),
             };
             let key = EcKey {
                 label: String::from(alias),
                 curve,
-                x,
-                y,
+                x: hex("x")?,
+                y: hex("y")?,
             };
-            Some(Key::Ec(key))
+            Ok(Some(Key::Ec(key)))
         }
-        _ => None,
+        _ => Ok(None),
     }
 }
 
@@ -73,3 +102,58 @@ impl HexValue for serde_json::Value {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RSA: &str =
+        r#"{"alias":"rsa","algorithm":"RSA","size":2048,"modulus":"c3","exponent":"10001"}"#;
+    const AES: &str = r#"{"alias":"aes","algorithm":"AES","size":256}"#;
+    const BROKEN: &str = r#"{"alias":"broken","algorithm":"RSA","size":2048,"modulus":"xyz"}"#;
+
+    fn list(keys: &[&str]) -> Result<Vec<Key>, Error> {
+        to_list(format!("[{}]", keys.join(",")))
+    }
+
+    #[test]
+    fn parses_keys() {
+        let keys = list(&[RSA]).unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].label(), "rsa");
+        match &keys[0] {
+            Key::Rsa(key) => assert_eq!(key.exponent(), [0x01, 0x00, 0x01]),
+            Key::Ec(_) => panic!("expected an RSA key"),
+        }
+    }
+
+    #[test]
+    fn skips_unsupported_keys() {
+        assert_eq!(list(&[AES, RSA]).unwrap().len(), 1);
+        assert!(list(&[AES]).unwrap().is_empty());
+        assert!(list(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn skips_invalid_keys_next_to_valid_ones() {
+        assert_eq!(list(&[BROKEN, RSA]).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn fails_if_no_key_is_valid() {
+        match list(&[AES, BROKEN]) {
+            Err(Error::InvalidResponse(reason)) => assert_eq!(reason, "broken: invalid modulus"),
+            _ => panic!("expected InvalidResponse"),
+        }
+        let result = list(&[r#"{"algorithm":"RSA"}"#]);
+        assert!(matches!(result, Err(Error::InvalidResponse(_))));
+    }
+
+    #[test]
+    fn fails_if_not_an_array() {
+        assert!(matches!(
+            to_list(String::from("{}")),
+            Err(Error::InvalidResponse(_))
+        ));
+    }
+}
@@ -1,8 +1,15 @@
 //! Module to represent a single public key. Also provides functionality
-//! build the key objects from JSON and to sign using these keys.
+//! build the key objects from JSON and to sign and verify using these keys.
 
 mod asn1;
 mod json;
+mod verify;
+
+use sha1::{Digest, Sha1};
+
+use crate::backend;
+use crate::bridge::Cancellation;
+use crate::error::Error;
 
 pub use json::to_list as json_to_list;
 
@@ -34,6 +41,12 @@ pub enum EcCurve {
     P521,
 }
 
+/// Fetches all the keys from the keystore backend.
+pub fn list() -> Result<Vec<Key>, Error> {
+    let json = backend::get()?.list_keys()?;
+    json_to_list(json)
+}
+
 impl Key {
     /// Returns the human-readable label of this key.
     pub fn label(&self) -> &str {
@@ -43,11 +56,59 @@ impl Key {
         }
     }
 
-    /// Signs data using this key.
-    pub fn sign(&self, data: &[u8]) -> Option<Vec<u8>> {
+    /// Returns the identifier of this key, which is the SHA-1 hash of its public key.
+    /// The public key is hashed in the same form X.509 subject key identifiers use:
+    /// the RSAPublicKey structure for RSA keys and the uncompressed point for EC keys.
+    /// Returns `None` if the public key cannot be encoded.
+    pub fn id(&self) -> Option<Vec<u8>> {
+        let public_key = match self {
+            Key::Rsa(key) => key.public_key_as_asn1().ok()?,
+            Key::Ec(key) => key.point(),
+        };
+        Some(Sha1::digest(public_key).to_vec())
+    }
+
+    /// Returns the size of this key in bits. This is the modulus length for RSA keys,
+    /// and the curve size for EC keys.
+    pub fn size(&self) -> usize {
+        match self {
+            Key::Rsa(key) => {
+                // Skip the leading zeroes so that only the significant bits are counted.
+                let start = key.modulus.iter().position(|&b| b != 0);
+                let modulus = &key.modulus[start.unwrap_or(key.modulus.len())..];
+                match modulus.first() {
+                    Some(first) => modulus.len() * 8 - first.leading_zeros() as usize,
+                    None => 0,
+                }
+            }
+            Key::Ec(key) => match key.curve {
+                EcCurve::P256 => 256,
+                EcCurve::P384 => 384,
+                EcCurve::P521 => 521,
+            },
+        }
+    }
+
+    /// Returns the length of the signatures created by this key, in bytes.
+    pub fn signature_len(&self) -> usize {
+        match self {
+            Key::Rsa(_) => self.size().div_ceil(8),
+            Key::Ec(key) => 2 * key.curve.param_length(),
+        }
+    }
+
+    /// Signs data using this key. Algorithm parameter must be in the format
+    /// that keystore expects (e.g. "SHA512withRSA"). Signing stops early if the
+    /// cancellation is triggered.
+    pub fn sign(
+        &self,
+        algorithm: &str,
+        data: &[u8],
+        cancellation: &Cancellation,
+    ) -> Result<Vec<u8>, Error> {
         match self {
-            Key::Rsa(key) => key.sign(data),
-            Key::Ec(key) => key.sign(data),
+            Key::Rsa(key) => key.sign(algorithm, data, cancellation),
+            Key::Ec(key) => key.sign(algorithm, data, cancellation),
         }
     }
 }
@@ -63,14 +124,27 @@ impl RsaKey {
         &self.exponent
     }
 
-    pub fn sign(&self, data: &[u8]) -> Option<Vec<u8>> {
-        super::bridge::sign(&self.label, "NONEwithRSA", data).ok()
+    pub fn sign(
+        &self,
+        algorithm: &str,
+        data: &[u8],
+        cancellation: &Cancellation,
+    ) -> Result<Vec<u8>, Error> {
+        backend::get()?.sign(&self.label, algorithm, data, cancellation)
     }
 }
 
 impl EcKey {
-    pub fn sign(&self, data: &[u8]) -> Option<Vec<u8>> {
-        let sign = super::bridge::sign(&self.label, "NONEwithECDSA", data).ok()?;
+    /// Signs data using this key. Keystore encodes the signature in ASN.1,
+    /// the returned signature is the concatenation of r and s instead.
+    pub fn sign(
+        &self,
+        algorithm: &str,
+        data: &[u8],
+        cancellation: &Cancellation,
+    ) -> Result<Vec<u8>, Error> {
+        let sign = backend::get()?.sign(&self.label, algorithm, data, cancellation)?;
         self.signature_from_asn1(&sign)
+            .ok_or_else(|| Error::InvalidResponse(String::from("invalid ECDSA signature")))
     }
 }
@@ -0,0 +1,68 @@
+//! Implementations for verifying signatures locally, using only the public key
+//! material. Verification never reaches the keystore.
+
+use p256::ecdsa::signature::hazmat::PrehashVerifier;
+use rsa::{BigUint, RsaPublicKey};
+
+use super::{EcCurve, EcKey, Key, RsaKey};
+use crate::algorithm::{ec_prehash, Algorithm, Scheme};
+
+impl Key {
+    /// Verifies the signature of the data. The signature must be in the same format
+    /// that `sign` returns. Algorithm parameter must be in the format that keystore
+    /// expects (e.g. "SHA512withRSA"). Returns false if the signature is not valid,
+    /// or if the algorithm cannot be used with this key.
+    pub fn verify(&self, algorithm: &str, data: &[u8], signature: &[u8]) -> bool {
+        let algorithm = match Algorithm::from_name(algorithm) {
+            Some(algorithm) => algorithm,
+            None => return false,
+        };
+        match self {
+            Key::Rsa(key) => key.verify(algorithm, data, signature),
+            Key::Ec(key) => key.verify(algorithm, data, signature),
+        }
+        .is_some()
+    }
+}
+
+impl RsaKey {
+    fn verify(&self, algorithm: Algorithm, data: &[u8], signature: &[u8]) -> Option<()> {
+        let modulus = BigUint::from_bytes_be(&self.modulus);
+        let exponent = BigUint::from_bytes_be(&self.exponent);
+        let key = RsaPublicKey::new(modulus, exponent).ok()?;
+        let hash = algorithm.digest.apply(data);
+        match algorithm.scheme {
+            Scheme::RsaPkcs1 => key.verify(algorithm.digest.pkcs1v15(), &hash, signature),
+            Scheme::RsaPss(digest) => key.verify(digest.pss()?, &hash, signature),
+            Scheme::Ecdsa => return None,
+        }
+        .ok()
+    }
+}
+
+impl EcKey {
+    fn verify(&self, algorithm: Algorithm, data: &[u8], signature: &[u8]) -> Option<()> {
+        if !matches!(algorithm.scheme, Scheme::Ecdsa) {
+            return None;
+        }
+        let hash = ec_prehash(algorithm.digest.apply(data), self.curve.param_length());
+        let point = self.point();
+        match self.curve {
+            EcCurve::P256 => {
+                let key = p256::ecdsa::VerifyingKey::from_sec1_bytes(&point).ok()?;
+                let signature = p256::ecdsa::Signature::from_slice(signature).ok()?;
+                key.verify_prehash(&hash, &signature).ok()
+            }
+            EcCurve::P384 => {
+                let key = p384::ecdsa::VerifyingKey::from_sec1_bytes(&point).ok()?;
+                let signature = p384::ecdsa::Signature::from_slice(signature).ok()?;
+                key.verify_prehash(&hash, &signature).ok()
+            }
+            EcCurve::P521 => {
+                let key = p521::ecdsa::VerifyingKey::from_sec1_bytes(&point).ok()?;
+                let signature = p521::ecdsa::Signature::from_slice(signature).ok()?;
+                key.verify_prehash(&hash, &signature).ok()
+            }
+        }
+    }
+}
@@ -1,144 +1,214 @@
 //! tergent - a cryptoki/PKCS#11 implementation that uses Android keystore as its backend.
+//!
+//! The `C_` functions are the entry points of the library. Their safety requirements are
+//! the ones described by the PKCS#11 standard: the pointers given to them must either be
+//! null, or point to valid memory of the length that accompanies them.
 
+// The entry points share the safety requirements above, instead of repeating them.
+#![allow(clippy::missing_safety_doc)]
+
+mod algorithm;
+mod backend;
 mod bridge;
+mod config;
+mod error;
+mod ffi;
 mod key;
+mod library;
+mod mechanism;
 mod pkcs11;
 mod state;
 
-use std::convert::{TryFrom, TryInto};
+use std::convert::TryFrom;
 use std::os::raw::{c_uchar, c_ulong, c_void};
-use std::{slice, str};
+use std::{ptr, slice, str};
 
 use key::Key;
 use pkcs11::*;
+use state::Object;
 
 /// tergent only provides a single slot, and this is its id. It is arbitrarily chosen.
 /// To comply with the standard we need to be consistent on which id we use for this slot.
 const SLOT_ID: c_ulong = 10;
 
 #[no_mangle]
-pub extern "C" fn C_Initialize(_init_args: *mut c_void) -> c_ulong {
-    ReturnValue::Ok.try_into().unwrap()
+pub unsafe extern "C" fn C_Initialize(init_args: *mut c_void) -> c_ulong {
+    ffi::guard(|| {
+        let init_args = unsafe { (init_args as *const CInitializeArgs).as_ref() };
+        library::initialize(init_args)
+    })
 }
 
 #[no_mangle]
-pub extern "C" fn C_Finalize(_reserved: *mut c_void) -> c_ulong {
-    ReturnValue::Ok.try_into().unwrap()
+pub extern "C" fn C_Finalize(reserved: *mut c_void) -> c_ulong {
+    ffi::guard(|| {
+        if !reserved.is_null() {
+            return Err(ReturnValue::ArgumentsBad);
+        }
+        library::finalize()
+    })
 }
 
 #[no_mangle]
-pub extern "C" fn C_GetInfo(info: *mut Info) -> c_ulong {
-    let mut info = unsafe { &mut *info };
-    info.cryptoki_version.major = CRYPTOKI_VERSION_MAJOR.try_into().unwrap();
-    info.cryptoki_version.minor = CRYPTOKI_VERSION_MINOR.try_into().unwrap();
-    copy_padded(&mut info.manufacturer_id, "tergent");
-    info.flags = 0;
-    copy_padded(&mut info.library_description, "tergent");
-    info.library_version.major = env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap();
-    info.library_version.minor = env!("CARGO_PKG_VERSION_MINOR").parse().unwrap();
-    ReturnValue::Ok.try_into().unwrap()
+pub unsafe extern "C" fn C_GetInfo(info: *mut Info) -> c_ulong {
+    ffi::guard(|| {
+        ffi::initialized()?;
+        let info = unsafe { ffi::reference(info) }?;
+        info.cryptoki_version.major = ffi::convert(CRYPTOKI_VERSION_MAJOR)?;
+        info.cryptoki_version.minor = ffi::convert(CRYPTOKI_VERSION_MINOR)?;
+        copy_padded(&mut info.manufacturer_id, "tergent");
+        info.flags = 0;
+        copy_padded(&mut info.library_description, "tergent");
+        info.library_version.major = env!("CARGO_PKG_VERSION_MAJOR")
+            .parse()
+            .map_err(|_| ReturnValue::GeneralError)?;
+        info.library_version.minor = env!("CARGO_PKG_VERSION_MINOR")
+            .parse()
+            .map_err(|_| ReturnValue::GeneralError)?;
+        Ok(())
+    })
 }
 
 #[no_mangle]
-pub extern "C" fn C_GetFunctionList(function_list: *mut *mut FunctionList) -> c_ulong {
-    unsafe {
-        *function_list = &mut FUNCTION_LIST as *mut FunctionList;
-    }
-    ReturnValue::Ok.try_into().unwrap()
+pub unsafe extern "C" fn C_GetFunctionList(function_list: *mut *mut FunctionList) -> c_ulong {
+    ffi::guard(|| {
+        let function_list = unsafe { ffi::reference(function_list) }?;
+        *function_list = ptr::addr_of_mut!(FUNCTION_LIST);
+        Ok(())
+    })
 }
 
 #[no_mangle]
-pub extern "C" fn C_GetSlotList(
+pub unsafe extern "C" fn C_GetSlotList(
     _token_present: c_uchar,
     slot_list: *mut c_ulong,
     count: *mut c_ulong,
 ) -> c_ulong {
-    // We only have a single slot.
-    let count = unsafe { &mut *count };
-    if !slot_list.is_null() {
-        if *count < 1 {
-            return ReturnValue::BufferTooSmall.try_into().unwrap();
-        }
-        unsafe {
-            *slot_list = SLOT_ID;
+    ffi::guard(|| {
+        // We only have a single slot.
+        ffi::initialized()?;
+        let count = unsafe { ffi::reference(count) }?;
+        if !slot_list.is_null() {
+            if *count < 1 {
+                *count = 1;
+                return Err(ReturnValue::BufferTooSmall);
+            }
+            unsafe {
+                *slot_list = SLOT_ID;
+            }
         }
-    }
-    *count = 1;
-    ReturnValue::Ok.try_into().unwrap()
+        *count = 1;
+        Ok(())
+    })
 }
 
 #[no_mangle]
-pub extern "C" fn C_GetSlotInfo(slot_id: c_ulong, info: *mut SlotInfo) -> c_ulong {
-    if slot_id != SLOT_ID {
-        return ReturnValue::SlotIdInvalid.try_into().unwrap();
-    }
-    let mut slot_info = unsafe { &mut *info };
-    copy_padded(&mut slot_info.slot_description, "tergent");
-    copy_padded(&mut slot_info.manufacturer_id, "tergent");
-    let flags = Flags::TOKEN_PRESENT | Flags::HW_SLOT;
-    slot_info.flags = flags.bits().into();
-    slot_info.hardware_version.major = 0;
-    slot_info.hardware_version.minor = 0;
-    slot_info.firmware_version.major = 0;
-    slot_info.firmware_version.minor = 0;
-    ReturnValue::Ok.try_into().unwrap()
-}
-
-#[no_mangle]
-pub extern "C" fn C_GetTokenInfo(slot_id: c_ulong, info: *mut TokenInfo) -> c_ulong {
-    if slot_id != SLOT_ID {
-        return ReturnValue::SlotIdInvalid.try_into().unwrap();
-    }
-
-    let session_count = match state::count().try_into() {
-        Ok(count) => count,
-        Err(_) => {
-            return ReturnValue::GeneralError.try_into().unwrap();
+pub unsafe extern "C" fn C_GetSlotInfo(slot_id: c_ulong, info: *mut SlotInfo) -> c_ulong {
+    ffi::guard(|| {
+        ffi::initialized()?;
+        if slot_id != SLOT_ID {
+            return Err(ReturnValue::SlotIdInvalid);
         }
-    };
-    let unavailable_information = UNAVAILABLE_INFORMATION as u64;
-
-    let mut token_info = unsafe { &mut *info };
-    copy_padded(&mut token_info.label, "tergent");
-    copy_padded(&mut token_info.manufacturer_id, "tergent");
-    copy_padded(&mut token_info.model, "tergent");
-    copy_padded(&mut token_info.serial_number, "");
-    token_info.flags = Flags::TOKEN_INITIALIZED.bits().into();
-    // Having simultaneous sessions is not yet supported.
-    token_info.max_session_count = 1;
-    token_info.session_count = session_count;
-    token_info.max_rw_session_count = 1;
-    token_info.rw_session_count = session_count;
-    token_info.max_pin_len = 0;
-    token_info.min_pin_len = 0;
-    token_info.total_public_memory = unavailable_information;
-    token_info.free_public_memory = unavailable_information;
-    token_info.total_private_memory = unavailable_information;
-    token_info.free_private_memory = unavailable_information;
-    token_info.hardware_version.major = 0;
-    token_info.hardware_version.minor = 0;
-    token_info.firmware_version.major = 0;
-    token_info.firmware_version.minor = 0;
-    copy_padded(&mut token_info.utc_time, "");
-    ReturnValue::Ok.try_into().unwrap()
-}
-
-#[no_mangle]
-pub extern "C" fn C_GetMechanismList(
-    _slot_id: c_ulong,
-    _mechanism_list: *mut c_ulong,
-    _count: *mut c_ulong,
+        let slot_info = unsafe { ffi::reference(info) }?;
+        copy_padded(&mut slot_info.slot_description, "tergent");
+        copy_padded(&mut slot_info.manufacturer_id, "tergent");
+        let flags = Flags::TOKEN_PRESENT | Flags::HW_SLOT;
+        slot_info.flags = flags.bits().into();
+        slot_info.hardware_version.major = 0;
+        slot_info.hardware_version.minor = 0;
+        slot_info.firmware_version.major = 0;
+        slot_info.firmware_version.minor = 0;
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_GetTokenInfo(slot_id: c_ulong, info: *mut TokenInfo) -> c_ulong {
+    ffi::guard(|| {
+        ffi::initialized()?;
+        if slot_id != SLOT_ID {
+            return Err(ReturnValue::SlotIdInvalid);
+        }
+        let token_info = unsafe { ffi::reference(info) }?;
+
+        let (session_count, rw_session_count) = state::count();
+        let unavailable_information = UNAVAILABLE_INFORMATION as c_ulong;
+
+        copy_padded(&mut token_info.label, "tergent");
+        copy_padded(&mut token_info.manufacturer_id, "tergent");
+        copy_padded(&mut token_info.model, "tergent");
+        copy_padded(&mut token_info.serial_number, "");
+        token_info.flags = Flags::TOKEN_INITIALIZED.bits().into();
+        token_info.max_session_count = EFFECTIVELY_INFINITE.into();
+        token_info.session_count = ffi::convert(session_count)?;
+        token_info.max_rw_session_count = EFFECTIVELY_INFINITE.into();
+        token_info.rw_session_count = ffi::convert(rw_session_count)?;
+        token_info.max_pin_len = 0;
+        token_info.min_pin_len = 0;
+        token_info.total_public_memory = unavailable_information;
+        token_info.free_public_memory = unavailable_information;
+        token_info.total_private_memory = unavailable_information;
+        token_info.free_private_memory = unavailable_information;
+        token_info.hardware_version.major = 0;
+        token_info.hardware_version.minor = 0;
+        token_info.firmware_version.major = 0;
+        token_info.firmware_version.minor = 0;
+        copy_padded(&mut token_info.utc_time, "");
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_GetMechanismList(
+    slot_id: c_ulong,
+    mechanism_list: *mut c_ulong,
+    count: *mut c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::guard(|| {
+        ffi::initialized()?;
+        if slot_id != SLOT_ID {
+            return Err(ReturnValue::SlotIdInvalid);
+        }
+        let count = unsafe { ffi::reference(count) }?;
+        // Only report the mechanisms that can be used with the keys in the keystore.
+        let keys = state::inventory::get()?;
+        let mechanisms = mechanism::list(keys.iter());
+        let mechanisms_len = ffi::convert(mechanisms.len())?;
+
+        if !mechanism_list.is_null() {
+            if *count < mechanisms_len {
+                *count = mechanisms_len;
+                return Err(ReturnValue::BufferTooSmall);
+            }
+            let mechanism_list = unsafe { ffi::slice_mut(mechanism_list, mechanisms_len) }?;
+            for (target, mechanism) in mechanism_list.iter_mut().zip(mechanisms) {
+                *target = ffi::convert(mechanism)?;
+            }
+        }
+        *count = mechanisms_len;
+        Ok(())
+    })
 }
 
 #[no_mangle]
-pub extern "C" fn C_GetMechanismInfo(
-    _slot_id: c_ulong,
-    _type_: c_ulong,
-    _info: *mut MechanismInfo,
+pub unsafe extern "C" fn C_GetMechanismInfo(
+    slot_id: c_ulong,
+    type_: c_ulong,
+    info: *mut MechanismInfo,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::guard(|| {
+        ffi::initialized()?;
+        if slot_id != SLOT_ID {
+            return Err(ReturnValue::SlotIdInvalid);
+        }
+        let info = unsafe { ffi::reference(info) }?;
+        let mechanism =
+            MechanismType::try_from(type_).map_err(|_| ReturnValue::MechanismInvalid)?;
+        let keys = state::inventory::get()?;
+        *info = mechanism::info(mechanism, keys.iter(), backend::get()?.as_ref())
+            .ok_or(ReturnValue::MechanismInvalid)?;
+        Ok(())
+    })
 }
 
 #[no_mangle]
@@ -148,12 +218,12 @@ pub extern "C" fn C_InitToken(
     _pin_len: c_ulong,
     _label: *mut c_uchar,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
 pub extern "C" fn C_InitPIN(_session: c_ulong, _pin: *mut c_uchar, _pin_len: c_ulong) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -164,11 +234,11 @@ pub extern "C" fn C_SetPIN(
     _new_pin: *mut c_uchar,
     _new_len: c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
-pub extern "C" fn C_OpenSession(
+pub unsafe extern "C" fn C_OpenSession(
     slot_id: c_ulong,
     flags: c_ulong,
     _application: *mut c_void,
@@ -177,50 +247,59 @@ pub extern "C" fn C_OpenSession(
     >,
     session: *mut c_ulong,
 ) -> c_ulong {
-    // Create a new state object, and return its index as the session id.
-    if slot_id != SLOT_ID {
-        return ReturnValue::SlotIdInvalid.try_into().unwrap();
-    }
-    let flags = match flags.try_into() {
-        Ok(flags) => flags,
-        Err(_) => {
-            return ReturnValue::ArgumentsBad.try_into().unwrap();
+    ffi::guard(|| {
+        // Create a new state object, and return its index as the session id.
+        ffi::initialized()?;
+        if slot_id != SLOT_ID {
+            return Err(ReturnValue::SlotIdInvalid);
         }
-    };
-    let flags = Flags::from_bits_truncate(flags);
-    if !flags.contains(Flags::SERIAL_SESSION) {
-        return ReturnValue::SessionParallelNotSupported.try_into().unwrap();
-    }
-    match state::new() {
-        Some(index) => {
-            unsafe { *session = index }
-            ReturnValue::Ok
+        let session = unsafe { ffi::reference(session) }?;
+        let flags = Flags::from_bits_truncate(ffi::argument(flags)?);
+        if !flags.contains(Flags::SERIAL_SESSION) {
+            return Err(ReturnValue::SessionParallelNotSupported);
         }
-        None => ReturnValue::GeneralError,
-    }
-    .try_into()
-    .unwrap()
+        let flags = flags & (Flags::RW_SESSION | Flags::SERIAL_SESSION);
+        *session = state::new(slot_id, flags)?;
+        Ok(())
+    })
 }
 
 #[no_mangle]
 pub extern "C" fn C_CloseSession(session: c_ulong) -> c_ulong {
-    // Free up the state associated with the given session.
-    match state::remove(session) {
-        Some(_) => ReturnValue::Ok,
-        None => ReturnValue::SessionHandleInvalid,
-    }
-    .try_into()
-    .unwrap()
+    ffi::guard(|| {
+        // Free up the state associated with the given session.
+        ffi::initialized()?;
+        state::remove(session).ok_or(ReturnValue::SessionHandleInvalid)?;
+        Ok(())
+    })
 }
 
 #[no_mangle]
-pub extern "C" fn C_CloseAllSessions(_slot_id: c_ulong) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+pub extern "C" fn C_CloseAllSessions(slot_id: c_ulong) -> c_ulong {
+    ffi::guard(|| {
+        ffi::initialized()?;
+        if slot_id != SLOT_ID {
+            return Err(ReturnValue::SlotIdInvalid);
+        }
+        state::remove_slot(slot_id);
+        Ok(())
+    })
 }
 
 #[no_mangle]
-pub extern "C" fn C_GetSessionInfo(_session: c_ulong, _info: *mut SessionInfo) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+pub unsafe extern "C" fn C_GetSessionInfo(session: c_ulong, info: *mut SessionInfo) -> c_ulong {
+    ffi::guard(|| {
+        // Report the details of the session that were recorded when it was opened.
+        ffi::initialized()?;
+        let state = ffi::session(session)?;
+        let state = ffi::lock(&state)?;
+        let session_info = unsafe { ffi::reference(info) }?;
+        session_info.slot_id = state.slot_id();
+        session_info.state = ffi::convert(state.session_state())?;
+        session_info.flags = state.flags().bits().into();
+        session_info.device_error = 0;
+        Ok(())
+    })
 }
 
 #[no_mangle]
@@ -229,7 +308,7 @@ pub extern "C" fn C_GetOperationState(
     _operation_state: *mut c_uchar,
     _operation_state_len: *mut c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -240,7 +319,7 @@ pub extern "C" fn C_SetOperationState(
     _encryption_key: c_ulong,
     _authentication_key: c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -250,12 +329,12 @@ pub extern "C" fn C_Login(
     _pin: *mut c_uchar,
     _pin_len: c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
 pub extern "C" fn C_Logout(_session: c_ulong) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -265,7 +344,7 @@ pub extern "C" fn C_CreateObject(
     _count: c_ulong,
     _object: *mut c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -276,12 +355,14 @@ pub extern "C" fn C_CopyObject(
     _count: c_ulong,
     _new_object: *mut c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
 pub extern "C" fn C_DestroyObject(_session: c_ulong, _object: c_ulong) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    // tergent does not delete keys yet. Once it does, the inventory must be refreshed
+    // afterwards, see `state::inventory::refresh`.
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -290,117 +371,68 @@ pub extern "C" fn C_GetObjectSize(
     _object: c_ulong,
     _size: *mut c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
-pub extern "C" fn C_GetAttributeValue(
+pub unsafe extern "C" fn C_GetAttributeValue(
     session: c_ulong,
     object: c_ulong,
     template: *mut Attribute,
     count: c_ulong,
 ) -> c_ulong {
-    // Main function that is used to query the details of a key.
-    let state = state::get(session);
-    let state = match state {
-        Some(state) => state,
-        None => {
-            return ReturnValue::SessionHandleInvalid.try_into().unwrap();
-        }
-    };
-    let state = state.lock().unwrap();
-    let key = match state.get_key(object as usize) {
-        Some(key) => key,
-        None => {
-            return ReturnValue::ObjectHandleInvalid.try_into().unwrap();
-        }
-    };
-
-    let count = match count.try_into() {
-        Ok(count) => count,
-        Err(_) => {
-            return ReturnValue::ArgumentsBad.try_into().unwrap();
-        }
-    };
-    let templates = unsafe { slice::from_raw_parts_mut(template, count) };
-    let mut type_invalid = false;
-    for template in templates {
-        let attribute_type = match AttributeType::try_from(template.type_) {
-            Ok(attribute_type) => attribute_type,
-            Err(_) => {
-                template.set_value_single(UNAVAILABLE_INFORMATION as u64);
-                type_invalid = true;
-                continue;
-            }
-        };
-        match attribute_type {
-            AttributeType::KeyType => {
-                let key_type = match key {
-                    Key::Rsa(_) => KeyType::Rsa,
-                    Key::Ec(_) => KeyType::Ec,
-                }
-                .try_into()
-                .unwrap();
-                template.set_value_single(key_type);
-            }
-            AttributeType::Label | AttributeType::Id => {
-                let label = key.label().as_bytes();
-                template.set_value(&label);
-            }
-            AttributeType::Modulus => {
-                if let Key::Rsa(key) = key {
-                    template.set_value(&key.modulus());
-                } else {
-                    template.set_value_single(UNAVAILABLE_INFORMATION as u64);
-                    type_invalid = true;
-                }
-            }
-            AttributeType::PublicExponent => {
-                if let Key::Rsa(key) = key {
-                    template.set_value(&key.exponent());
-                } else {
-                    template.set_value_single(UNAVAILABLE_INFORMATION as u64);
+    ffi::guard(|| {
+        // Main function that is used to query the details of a key.
+        ffi::initialized()?;
+        let state = ffi::session(session)?;
+        let state = ffi::lock(&state)?;
+        let object = Object::from_handle(object).ok_or(ReturnValue::ObjectHandleInvalid)?;
+        let key = state
+            .get_key(object)
+            .ok_or(ReturnValue::ObjectHandleInvalid)?;
+        let templates = unsafe { ffi::slice_mut(template, count) }?;
+        let backend = backend::get()?;
+        let mut sensitive = false;
+        let mut type_invalid = false;
+        let mut buffer_too_small = false;
+        for template in templates {
+            let attribute_type = match AttributeType::try_from(template.type_) {
+                Ok(attribute_type) => attribute_type,
+                Err(_) => {
+                    template.set_unavailable();
                     type_invalid = true;
+                    continue;
                 }
+            };
+            if object.is_sensitive(key, attribute_type) {
+                template.set_unavailable();
+                sensitive = true;
+                continue;
             }
-            AttributeType::EcPoint => {
-                if let Key::Ec(key) = key {
-                    let point = key.point_as_asn1();
-                    if let Ok(point) = point {
-                        template.set_value(&point);
-                        continue;
+            match object.attribute(key, attribute_type, backend.as_ref()) {
+                Some(value) => {
+                    if template.set_value(&value).is_none() {
+                        buffer_too_small = true;
                     }
                 }
-                template.set_value_single(UNAVAILABLE_INFORMATION as u64);
-                type_invalid = true;
-            }
-            AttributeType::EcParams => {
-                if let Key::Ec(key) = key {
-                    let params = key.params_as_asn1();
-                    if let Ok(params) = params {
-                        template.set_value(&params);
-                        continue;
-                    }
+                None => {
+                    template.set_unavailable();
+                    type_invalid = true;
                 }
-                template.set_value_single(UNAVAILABLE_INFORMATION as u64);
-                type_invalid = true;
-            }
-            AttributeType::AlwaysAuthenticate => {
-                template.set_value_single(0);
-            }
-            _ => {
-                template.set_value_single(UNAVAILABLE_INFORMATION as u64);
-                type_invalid = true;
             }
-        };
-    }
-    if type_invalid {
-        ReturnValue::AttributeTypeInvalid
-    } else {
-        ReturnValue::Ok
-    }
-    .try_into()
-    .unwrap()
+        }
+        // All the attributes are processed even if some of them fail, as required by the
+        // standard. Any of the errors can be returned, the more serious ones are preferred.
+        if sensitive {
+            Err(ReturnValue::AttributeSensitive)
+        } else if type_invalid {
+            Err(ReturnValue::AttributeTypeInvalid)
+        } else if buffer_too_small {
+            Err(ReturnValue::BufferTooSmall)
+        } else {
+            Ok(())
+        }
+    })
 }
 
 #[no_mangle]
@@ -410,112 +442,65 @@ pub extern "C" fn C_SetAttributeValue(
     _template: *mut Attribute,
     _count: c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
-pub extern "C" fn C_FindObjectsInit(
+pub unsafe extern "C" fn C_FindObjectsInit(
     session: c_ulong,
     template: *mut Attribute,
     count: c_ulong,
 ) -> c_ulong {
-    // Initialize a search operation.
-    let mut find_keys = false;
-    let mut find_id = None;
-
-    let count = match count.try_into() {
-        Ok(count) => count,
-        Err(_) => {
-            return ReturnValue::ArgumentsBad.try_into().unwrap();
-        }
-    };
-    let templates = unsafe { slice::from_raw_parts_mut(template, count) };
-    for template in templates {
-        match AttributeType::try_from(template.type_) {
-            Ok(AttributeType::Class) => {
-                // We only support searching for public/private keys for now.
-                let value = template.value as *mut u64;
-                let class = unsafe { *value };
-                if let Ok(ObjectClass::PublicKey) | Ok(ObjectClass::PrivateKey) = class.try_into() {
-                    find_keys = true;
-                }
-            }
-            Ok(AttributeType::Id) => {
-                // The application wants to search for a specific key with the given id/label.
-                let value = template.value as *mut u8;
-                let length = template.value_len.try_into().unwrap();
-                let id = unsafe { slice::from_raw_parts_mut(value, length) };
-                if let Ok(id) = str::from_utf8(id) {
-                    find_id = Some(String::from(id));
-                } else {
-                    return ReturnValue::AttributeValueInvalid.try_into().unwrap();
-                }
-            }
-            Ok(AttributeType::Sign) => {
-                // All the keys can be used for signing, so ignore this attribute.
-            }
-            _ => {
-                // Unknown attribute, abort the operation.
-                return ReturnValue::AttributeTypeInvalid.try_into().unwrap();
-            }
-        }
-    }
-    let state = state::get(session);
-    let state = match state {
-        Some(state) => state,
-        None => {
-            return ReturnValue::SessionHandleInvalid.try_into().unwrap();
+    ffi::guard(|| {
+        // Initialize a search operation.
+        ffi::initialized()?;
+        let templates = unsafe { ffi::slice(template, count) }?;
+        let mut find_template = Vec::new();
+        for template in templates {
+            let value = template.value as *const u8;
+            let value = unsafe { ffi::slice(value, template.value_len) }?;
+            find_template.push((template.type_, value.to_vec()));
         }
-    };
-    let mut state = state.lock().unwrap();
-    state.find_init(find_keys, find_id);
-
-    ReturnValue::Ok.try_into().unwrap()
+        let state = ffi::session(session)?;
+        let mut state = ffi::lock(&state)?;
+        state.find_init(&find_template, backend::get()?.as_ref())
+    })
 }
 
 #[no_mangle]
-pub extern "C" fn C_FindObjects(
+pub unsafe extern "C" fn C_FindObjects(
     session: c_ulong,
     object: *mut c_ulong,
     max_object_count: c_ulong,
     object_count: *mut c_ulong,
 ) -> c_ulong {
-    // Continue a search operation.
-    let object_count = unsafe { &mut *object_count };
-    *object_count = 0;
-
-    let state = state::get(session);
-    let state = match state {
-        Some(state) => state,
-        None => {
-            return ReturnValue::SessionHandleInvalid.try_into().unwrap();
-        }
-    };
-    let mut state = state.lock().unwrap();
-
-    let max_object_count = match max_object_count.try_into() {
-        Ok(max_object_count) => max_object_count,
-        Err(_) => {
-            return ReturnValue::ArgumentsBad.try_into().unwrap();
+    ffi::guard(|| {
+        // Continue a search operation.
+        ffi::initialized()?;
+        let object_count = unsafe { ffi::reference(object_count) }?;
+        *object_count = 0;
+        let objects = unsafe { ffi::slice_mut(object, max_object_count) }?;
+
+        let state = ffi::session(session)?;
+        let mut state = ffi::lock(&state)?;
+        let found = state.find_next(objects.len())?;
+        for (object, found) in objects.iter_mut().zip(found) {
+            *object = found.handle().ok_or(ReturnValue::GeneralError)?;
+            *object_count += 1;
         }
-    };
-    let objects = unsafe { slice::from_raw_parts_mut(object, max_object_count) };
-    for object in objects {
-        let key_index = state.find_next();
-        match key_index {
-            Some(index) => {
-                *object = index.try_into().unwrap();
-                *object_count += 1;
-            }
-            None => break,
-        }
-    }
-    ReturnValue::Ok.try_into().unwrap()
+        Ok(())
+    })
 }
 
 #[no_mangle]
-pub extern "C" fn C_FindObjectsFinal(_session: c_ulong) -> c_ulong {
-    ReturnValue::Ok.try_into().unwrap()
+pub extern "C" fn C_FindObjectsFinal(session: c_ulong) -> c_ulong {
+    ffi::guard(|| {
+        // Terminate a search operation.
+        ffi::initialized()?;
+        let state = ffi::session(session)?;
+        let mut state = ffi::lock(&state)?;
+        state.find_final()
+    })
 }
 
 #[no_mangle]
@@ -524,7 +509,7 @@ pub extern "C" fn C_EncryptInit(
     _mechanism: *mut Mechanism,
     _key: c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -535,7 +520,7 @@ pub extern "C" fn C_Encrypt(
     _encrypted_data: *mut c_uchar,
     _encrypted_data_len: *mut c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -546,7 +531,7 @@ pub extern "C" fn C_EncryptUpdate(
     _encrypted_part: *mut c_uchar,
     _encrypted_part_len: *mut c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -555,7 +540,7 @@ pub extern "C" fn C_EncryptFinal(
     _last_encrypted_part: *mut c_uchar,
     _last_encrypted_part_len: *mut c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -564,7 +549,7 @@ pub extern "C" fn C_DecryptInit(
     _mechanism: *mut Mechanism,
     _key: c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -575,7 +560,7 @@ pub extern "C" fn C_Decrypt(
     _data: *mut c_uchar,
     _data_len: *mut c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -586,7 +571,7 @@ pub extern "C" fn C_DecryptUpdate(
     _part: *mut c_uchar,
     _part_len: *mut c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -595,12 +580,12 @@ pub extern "C" fn C_DecryptFinal(
     _last_part: *mut c_uchar,
     _last_part_len: *mut c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
 pub extern "C" fn C_DigestInit(_session: c_ulong, _mechanism: *mut Mechanism) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -611,7 +596,7 @@ pub extern "C" fn C_Digest(
     _digest: *mut c_uchar,
     _digest_len: *mut c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -620,12 +605,12 @@ pub extern "C" fn C_DigestUpdate(
     _part: *mut c_uchar,
     _part_len: c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
 pub extern "C" fn C_DigestKey(_session: c_ulong, _key: c_ulong) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -634,120 +619,179 @@ pub extern "C" fn C_DigestFinal(
     _digest: *mut c_uchar,
     _digest_len: *mut c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
-pub extern "C" fn C_SignInit(session: c_ulong, mechanism: *mut Mechanism, key: c_ulong) -> c_ulong {
-    // Initialize a sign operation.
-    let state = state::get(session);
-    let state = match state {
-        Some(state) => state,
-        None => {
-            return ReturnValue::SessionHandleInvalid.try_into().unwrap();
-        }
-    };
-    let mut state = state.lock().unwrap();
-
-    let index = key.try_into().unwrap();
-    let key = state.get_key(index);
-    let key = match key {
-        Some(key) => key,
-        None => {
-            return ReturnValue::KeyHandleInvalid.try_into().unwrap();
-        }
-    };
-    let mechanism = unsafe { &*mechanism };
-    // Android keystore supports more mechanisms but these two are the bare minimum required
-    // for ssh to function.
-    match MechanismType::try_from(mechanism.mechanism) {
-        Ok(MechanismType::Ecdsa) => {
-            if let key::Key::Ec(_) = key {
-                state.sign_init(index);
-            } else {
-                return ReturnValue::KeyTypeInconsistent.try_into().unwrap();
-            }
-        }
-        Ok(MechanismType::RsaPkcs) => {
-            if let key::Key::Rsa(_) = key {
-                state.sign_init(index);
-            } else {
-                return ReturnValue::KeyTypeInconsistent.try_into().unwrap();
+pub unsafe extern "C" fn C_SignInit(
+    session: c_ulong,
+    mechanism: *mut Mechanism,
+    key: c_ulong,
+) -> c_ulong {
+    ffi::guard(|| {
+        // Initialize a sign operation.
+        ffi::initialized()?;
+        let state = ffi::session(session)?;
+        let mut state = ffi::lock(&state)?;
+
+        // A null mechanism cancels the active sign operation.
+        let mechanism = match unsafe { mechanism.as_ref() } {
+            Some(mechanism) => mechanism,
+            None => {
+                state.sign_cancel();
+                return Ok(());
             }
+        };
+        let object = Object::from_handle(key).ok_or(ReturnValue::KeyHandleInvalid)?;
+        let key = state.get_key(object).ok_or(ReturnValue::KeyHandleInvalid)?;
+        if object.class != ObjectClass::PrivateKey {
+            return Err(ReturnValue::KeyFunctionNotPermitted);
         }
-        _ => {
-            return ReturnValue::MechanismInvalid.try_into().unwrap();
+        let algorithm = mechanism_algorithm(mechanism, key)?;
+        if !backend::get()?.can_sign(algorithm) {
+            return Err(ReturnValue::MechanismInvalid);
         }
-    }
-    ReturnValue::Ok.try_into().unwrap()
+        state.sign_init(object.index, algorithm)
+    })
 }
 
 #[no_mangle]
-pub extern "C" fn C_Sign(
+pub unsafe extern "C" fn C_Sign(
     session: c_ulong,
     data: *mut c_uchar,
     data_len: c_ulong,
     signature: *mut c_uchar,
     signature_len: *mut c_ulong,
 ) -> c_ulong {
-    // Calculate the signature.
-    let signature_out = {
-        let data = unsafe { slice::from_raw_parts_mut(data, data_len.try_into().unwrap()) };
-
-        let state = state::get(session);
-        let state = match state {
-            Some(state) => state,
-            None => {
-                return ReturnValue::SessionHandleInvalid.try_into().unwrap();
+    ffi::guard(|| {
+        // Calculate the signature in a single part.
+        ffi::initialized()?;
+        let state = ffi::session(session)?;
+        let mut state = ffi::lock(&state)?;
+        let data = match unsafe { ffi::slice(data, data_len) } {
+            Ok(data) => data,
+            Err(return_value) => {
+                state.sign_cancel();
+                return Err(return_value);
             }
         };
-        let state = state.lock().unwrap();
+        unsafe {
+            write_signature(&mut state, signature, signature_len, |state| {
+                state.sign(data)
+            })
+        }
+    })
+}
 
-        let key = state.get_sign_key().unwrap();
-        if let Some(signature) = key.sign(data) {
-            signature
-        } else {
-            return ReturnValue::GeneralError.try_into().unwrap();
+#[no_mangle]
+pub unsafe extern "C" fn C_SignUpdate(
+    session: c_ulong,
+    part: *mut c_uchar,
+    part_len: c_ulong,
+) -> c_ulong {
+    ffi::guard(|| {
+        // Collect the data to be signed, the signature is calculated in C_SignFinal.
+        ffi::initialized()?;
+        let state = ffi::session(session)?;
+        let mut state = ffi::lock(&state)?;
+        match unsafe { ffi::slice(part, part_len) } {
+            Ok(part) => state.sign_update(part),
+            Err(return_value) => {
+                state.sign_cancel();
+                Err(return_value)
+            }
         }
-    };
-    // Size required to represent the signature output.
-    let signature_out_len = signature_out.len().try_into().unwrap();
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_SignFinal(
+    session: c_ulong,
+    signature: *mut c_uchar,
+    signature_len: *mut c_ulong,
+) -> c_ulong {
+    ffi::guard(|| {
+        // Calculate the signature of all the data collected by C_SignUpdate.
+        ffi::initialized()?;
+        let state = ffi::session(session)?;
+        let mut state = ffi::lock(&state)?;
+        unsafe {
+            write_signature(&mut state, signature, signature_len, |state| {
+                state.sign_final()
+            })
+        }
+    })
+}
+
+/// Places the signature created by `sign` in the buffer provided by the application.
+/// If there is no buffer, or if it is too small, only the required length is reported
+/// and `sign` is not called. This lets the application retry with a larger buffer
+/// without having to sign twice. Otherwise the sign operation is terminated.
+///
+/// # Safety
+///
+/// The pointers must be the ones given to `C_Sign` or `C_SignFinal` by the application.
+unsafe fn write_signature<F>(
+    state: &mut state::State,
+    signature: *mut c_uchar,
+    signature_len: *mut c_ulong,
+    sign: F,
+) -> Result<(), ReturnValue>
+where
+    F: FnOnce(&mut state::State) -> Result<Vec<u8>, ReturnValue>,
+{
+    let signature_out_len = state.get_sign_key()?.signature_len();
     // Size provided to us to place the signature output.
-    let signature_len = unsafe { &mut *signature_len };
+    let signature_len = match ffi::reference(signature_len) {
+        Ok(signature_len) => signature_len,
+        Err(return_value) => {
+            state.sign_cancel();
+            return Err(return_value);
+        }
+    };
+
+    let required_len = ffi::convert(signature_out_len)?;
 
     // No buffer is given, just tell the application how much space we need.
     if signature.is_null() {
-        *signature_len = signature_out_len;
-        return ReturnValue::Ok.try_into().unwrap();
+        *signature_len = required_len;
+        return Ok(());
     }
     // Buffer is too small.
-    if *signature_len < signature_out.len().try_into().unwrap() {
-        *signature_len = signature_out_len;
-        return ReturnValue::BufferTooSmall.try_into().unwrap();
+    if *signature_len < required_len {
+        *signature_len = required_len;
+        return Err(ReturnValue::BufferTooSmall);
     }
 
-    let signature = unsafe { slice::from_raw_parts_mut(signature, signature_out.len()) };
+    let signature_out = sign(state)?;
+    if signature_out.len() > signature_out_len {
+        return Err(ReturnValue::GeneralError);
+    }
+    let signature = slice::from_raw_parts_mut(signature, signature_out.len());
     signature.copy_from_slice(&signature_out);
-    *signature_len = signature_out_len;
-    ReturnValue::Ok.try_into().unwrap()
-}
-
-#[no_mangle]
-pub extern "C" fn C_SignUpdate(
-    _session: c_ulong,
-    _part: *mut c_uchar,
-    _part_len: c_ulong,
-) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    *signature_len = ffi::convert(signature_out.len())?;
+    Ok(())
 }
 
-#[no_mangle]
-pub extern "C" fn C_SignFinal(
-    _session: c_ulong,
-    _signature: *mut c_uchar,
-    _signature_len: *mut c_ulong,
-) -> c_ulong {
-    ReturnValue::Ok.try_into().unwrap()
+/// Checks that the mechanism is supported and that it can be used with the key.
+/// Returns the keystore signature algorithm that implements the mechanism.
+fn mechanism_algorithm(mechanism: &Mechanism, key: &Key) -> Result<&'static str, ReturnValue> {
+    let mechanism_type =
+        MechanismType::try_from(mechanism.mechanism).map_err(|_| ReturnValue::MechanismInvalid)?;
+    if !mechanism::is_supported(mechanism_type) {
+        return Err(ReturnValue::MechanismInvalid);
+    }
+    if !mechanism::for_key(key).contains(&mechanism_type) {
+        return Err(ReturnValue::KeyTypeInconsistent);
+    }
+    if !mechanism::is_pss(mechanism_type) {
+        return mechanism::algorithm(mechanism_type).ok_or(ReturnValue::MechanismInvalid);
+    }
+    // The mechanism is given by the application to C_SignInit or C_VerifyInit, and its
+    // parameter is only read while these run.
+    unsafe { mechanism.parameter::<RsaPkcsPssParams>() }
+        .and_then(|params| mechanism::pss_algorithm(mechanism_type, params))
+        .ok_or(ReturnValue::MechanismParamInvalid)
 }
 
 #[no_mangle]
@@ -756,7 +800,7 @@ pub extern "C" fn C_SignRecoverInit(
     _mechanism: *mut Mechanism,
     _key: c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -767,45 +811,108 @@ pub extern "C" fn C_SignRecover(
     _signature: *mut c_uchar,
     _signature_len: *mut c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
-pub extern "C" fn C_VerifyInit(
-    _session: c_ulong,
-    _mechanism: *mut Mechanism,
-    _key: c_ulong,
+pub unsafe extern "C" fn C_VerifyInit(
+    session: c_ulong,
+    mechanism: *mut Mechanism,
+    key: c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::guard(|| {
+        // Initialize a verify operation.
+        ffi::initialized()?;
+        let state = ffi::session(session)?;
+        let mut state = ffi::lock(&state)?;
+
+        // A null mechanism cancels the active verify operation.
+        let mechanism = match unsafe { mechanism.as_ref() } {
+            Some(mechanism) => mechanism,
+            None => {
+                state.verify_cancel();
+                return Ok(());
+            }
+        };
+        let object = Object::from_handle(key).ok_or(ReturnValue::KeyHandleInvalid)?;
+        let key = state.get_key(object).ok_or(ReturnValue::KeyHandleInvalid)?;
+        if object.class != ObjectClass::PublicKey {
+            return Err(ReturnValue::KeyFunctionNotPermitted);
+        }
+        let algorithm = mechanism_algorithm(mechanism, key)?;
+        state.verify_init(object.index, algorithm)
+    })
 }
 
 #[no_mangle]
-pub extern "C" fn C_Verify(
-    _session: c_ulong,
-    _data: *mut c_uchar,
-    _data_len: c_ulong,
-    _signature: *mut c_uchar,
-    _signature_len: c_ulong,
+pub unsafe extern "C" fn C_Verify(
+    session: c_ulong,
+    data: *mut c_uchar,
+    data_len: c_ulong,
+    signature: *mut c_uchar,
+    signature_len: c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::guard(|| {
+        // Verify the signature in a single part. This is done locally using the public key.
+        ffi::initialized()?;
+        let state = ffi::session(session)?;
+        let mut state = ffi::lock(&state)?;
+        let buffers = unsafe {
+            (
+                ffi::slice(data, data_len),
+                ffi::slice(signature, signature_len),
+            )
+        };
+        match buffers {
+            (Ok(data), Ok(signature)) => state.verify(data, signature),
+            (Err(return_value), _) | (_, Err(return_value)) => {
+                state.verify_cancel();
+                Err(return_value)
+            }
+        }
+    })
 }
 
 #[no_mangle]
-pub extern "C" fn C_VerifyUpdate(
-    _session: c_ulong,
-    _part: *mut c_uchar,
-    _part_len: c_ulong,
+pub unsafe extern "C" fn C_VerifyUpdate(
+    session: c_ulong,
+    part: *mut c_uchar,
+    part_len: c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::guard(|| {
+        // Collect the data to be verified, the signature is checked in C_VerifyFinal.
+        ffi::initialized()?;
+        let state = ffi::session(session)?;
+        let mut state = ffi::lock(&state)?;
+        match unsafe { ffi::slice(part, part_len) } {
+            Ok(part) => state.verify_update(part),
+            Err(return_value) => {
+                state.verify_cancel();
+                Err(return_value)
+            }
+        }
+    })
 }
 
 #[no_mangle]
-pub extern "C" fn C_VerifyFinal(
-    _session: c_ulong,
-    _signature: *mut c_uchar,
-    _signature_len: c_ulong,
+pub unsafe extern "C" fn C_VerifyFinal(
+    session: c_ulong,
+    signature: *mut c_uchar,
+    signature_len: c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::guard(|| {
+        // Verify the signature of all the data collected by C_VerifyUpdate.
+        ffi::initialized()?;
+        let state = ffi::session(session)?;
+        let mut state = ffi::lock(&state)?;
+        match unsafe { ffi::slice(signature, signature_len) } {
+            Ok(signature) => state.verify_final(signature),
+            Err(return_value) => {
+                state.verify_cancel();
+                Err(return_value)
+            }
+        }
+    })
 }
 
 #[no_mangle]
@@ -814,7 +921,7 @@ pub extern "C" fn C_VerifyRecoverInit(
     _mechanism: *mut Mechanism,
     _key: c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -825,7 +932,7 @@ pub extern "C" fn C_VerifyRecover(
     _data: *mut c_uchar,
     _data_len: *mut c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -836,7 +943,7 @@ pub extern "C" fn C_DigestEncryptUpdate(
     _encrypted_part: *mut c_uchar,
     _encrypted_part_len: *mut c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -847,7 +954,7 @@ pub extern "C" fn C_DecryptDigestUpdate(
     _part: *mut c_uchar,
     _part_len: *mut c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -858,7 +965,7 @@ pub extern "C" fn C_SignEncryptUpdate(
     _encrypted_part: *mut c_uchar,
     _encrypted_part_len: *mut c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -869,7 +976,7 @@ pub extern "C" fn C_DecryptVerifyUpdate(
     _part: *mut c_uchar,
     _part_len: *mut c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -880,7 +987,7 @@ pub extern "C" fn C_GenerateKey(
     _count: c_ulong,
     _key: *mut c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -894,7 +1001,9 @@ pub extern "C" fn C_GenerateKeyPair(
     _public_key: *mut c_ulong,
     _private_key: *mut c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    // tergent does not generate keys yet. Once it does, the inventory must be refreshed
+    // afterwards, see `state::inventory::refresh`.
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -906,7 +1015,7 @@ pub extern "C" fn C_WrapKey(
     _wrapped_key: *mut c_uchar,
     _wrapped_key_len: *mut c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -920,7 +1029,7 @@ pub extern "C" fn C_UnwrapKey(
     _attribute_count: c_ulong,
     _key: *mut c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -932,7 +1041,7 @@ pub extern "C" fn C_DeriveKey(
     _attribute_count: c_ulong,
     _key: *mut c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -941,7 +1050,7 @@ pub extern "C" fn C_SeedRandom(
     _seed: *mut c_uchar,
     _seed_len: c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
@@ -950,17 +1059,22 @@ pub extern "C" fn C_GenerateRandom(
     _random_data: *mut c_uchar,
     _random_len: c_ulong,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
 pub extern "C" fn C_GetFunctionStatus(_session: c_ulong) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 #[no_mangle]
-pub extern "C" fn C_CancelFunction(_session: c_ulong) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+pub extern "C" fn C_CancelFunction(session: c_ulong) -> c_ulong {
+    ffi::guard(|| {
+        // Stop the keystore call that another thread is making for this session. The
+        // interrupted function returns FunctionCanceled.
+        ffi::initialized()?;
+        state::cancel(session).ok_or(ReturnValue::SessionHandleInvalid)
+    })
 }
 
 #[no_mangle]
@@ -969,7 +1083,7 @@ pub extern "C" fn C_WaitForSlotEvent(
     _slot: *mut c_ulong,
     _rserved: *mut c_void,
 ) -> c_ulong {
-    ReturnValue::FunctionNotSupported.try_into().unwrap()
+    ffi::not_supported()
 }
 
 static mut FUNCTION_LIST: FunctionList = FunctionList {
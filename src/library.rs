@@ -0,0 +1,149 @@
+//! Keeps track of the lifecycle of the library. The standard requires almost every
+//! function to fail unless `C_Initialize` has been called, and `C_Finalize` to clean
+//! up everything so that the library can be initialized again later.
+
+use std::convert::TryFrom;
+use std::os::raw::{c_ulong, c_void};
+use std::ptr;
+use std::sync::{Arc, Mutex};
+
+use crate::config;
+use crate::pkcs11::{CInitializeArgs, Flags, ReturnValue};
+use crate::state;
+
+/// The locking mode of the library. `None` if the library is not initialized.
+static LOCKING: Mutex<Option<Locking>> = Mutex::new(None);
+
+/// How the library protects its shared data from concurrent access.
+#[derive(Clone)]
+enum Locking {
+    /// The native locking primitives of the operating system are used.
+    Os,
+    /// The mutex callbacks provided by the application are used, as the application
+    /// did not allow the use of the native locking primitives. The calls in progress
+    /// hold a reference to the mutex, so that it outlives them even if the library is
+    /// finalized meanwhile.
+    Application(Arc<ApplicationMutex>),
+}
+
+/// A mutex created through the callbacks of the application, which is destroyed
+/// when it is dropped.
+struct ApplicationMutex {
+    destroy: unsafe extern "C" fn(*mut c_void) -> c_ulong,
+    lock: unsafe extern "C" fn(*mut c_void) -> c_ulong,
+    unlock: unsafe extern "C" fn(*mut c_void) -> c_ulong,
+    mutex: *mut c_void,
+}
+
+// The application guarantees that its mutexes can be used from any thread.
+unsafe impl Send for ApplicationMutex {}
+unsafe impl Sync for ApplicationMutex {}
+
+/// Initializes the library with the arguments given to `C_Initialize`, which are optional.
+/// tergent never creates any threads, so `LIBRARY_CANT_CREATE_OS_THREADS` is always
+/// satisfied.
+pub fn initialize(args: Option<&CInitializeArgs>) -> Result<(), ReturnValue> {
+    let mut locking = LOCKING.lock().map_err(|_| ReturnValue::GeneralError)?;
+    if locking.is_some() {
+        return Err(ReturnValue::CryptokiAlreadyInitialized);
+    }
+    // The configuration is validated here, so that the mistakes in it are reported
+    // right away. The return value cannot tell what is wrong, so it is printed too.
+    if let Err(error) = config::load() {
+        eprintln!("tergent: {}", error);
+        return Err(error.into());
+    }
+    *locking = Some(match args {
+        Some(args) => Locking::from_args(args)?,
+        None => Locking::Os,
+    });
+    Ok(())
+}
+
+/// Finalizes the library, closing all the sessions.
+pub fn finalize() -> Result<(), ReturnValue> {
+    if !is_initialized() {
+        return Err(ReturnValue::CryptokiNotInitialized);
+    }
+    // The states are cleared before the locking mode is reset, so that the
+    // application mutex is still used while doing so.
+    state::clear();
+    state::inventory::clear();
+    let mut locking = LOCKING.lock().map_err(|_| ReturnValue::GeneralError)?;
+    // The application mutex is destroyed once the calls still using it are over.
+    *locking = None;
+    Ok(())
+}
+
+/// Returns whether the library is initialized.
+pub fn is_initialized() -> bool {
+    match LOCKING.lock() {
+        Ok(locking) => locking.is_some(),
+        Err(_) => false,
+    }
+}
+
+/// Runs the given function while holding the mutex of the application, if the
+/// application has asked the library to use its own mutexes. Otherwise the function
+/// is run as is, and it is expected to use the native locking primitives.
+pub fn synchronized<T, F: FnOnce() -> T>(function: F) -> T {
+    // The locking mode is copied so that `LOCKING` is not held while running the
+    // function, which may need to check whether the library is initialized.
+    let locking = match LOCKING.lock() {
+        Ok(locking) => locking.clone(),
+        Err(_) => None,
+    };
+    match locking {
+        Some(Locking::Application(mutex)) => {
+            unsafe { (mutex.lock)(mutex.mutex) };
+            let result = function();
+            unsafe { (mutex.unlock)(mutex.mutex) };
+            result
+        }
+        _ => function(),
+    }
+}
+
+impl Locking {
+    /// Selects the locking mode as described by the arguments of `C_Initialize`.
+    fn from_args(args: &CInitializeArgs) -> Result<Self, ReturnValue> {
+        if !args.reserved.is_null() {
+            return Err(ReturnValue::ArgumentsBad);
+        }
+        let flags = Flags::from_bits_truncate(u32::try_from(args.flags).unwrap_or(0));
+        let callbacks = (
+            args.create_mutex,
+            args.destroy_mutex,
+            args.lock_mutex,
+            args.unlockmutex,
+        );
+        match callbacks {
+            // The application either provides all of the callbacks, or none of them.
+            (None, None, None, None) => Ok(Locking::Os),
+            (Some(_), Some(_), Some(_), Some(_)) if flags.contains(Flags::OS_LOCKING_OK) => {
+                Ok(Locking::Os)
+            }
+            (Some(create), Some(destroy), Some(lock), Some(unlock)) => {
+                let mut mutex = ptr::null_mut();
+                match ReturnValue::try_from(unsafe { create(&mut mutex) }) {
+                    Ok(ReturnValue::Ok) => {}
+                    Ok(return_value) => return Err(return_value),
+                    Err(_) => return Err(ReturnValue::GeneralError),
+                }
+                Ok(Locking::Application(Arc::new(ApplicationMutex {
+                    destroy,
+                    lock,
+                    unlock,
+                    mutex,
+                })))
+            }
+            _ => Err(ReturnValue::ArgumentsBad),
+        }
+    }
+}
+
+impl Drop for ApplicationMutex {
+    fn drop(&mut self) {
+        unsafe { (self.destroy)(self.mutex) };
+    }
+}
@@ -0,0 +1,256 @@
+//! Describes the mechanisms that tergent supports, and which keys can be used with them.
+
+use std::convert::{TryFrom, TryInto};
+use std::os::raw::c_ulong;
+
+use crate::backend::Backend;
+use crate::key::Key;
+use crate::pkcs11::{
+    Flags, MaskGenerationFunction, MechanismInfo, MechanismType, RsaPkcsPssParams,
+};
+
+/// Mechanisms that can be used with RSA keys.
+/// Variants with SHA-224 are left out as keystore keys are not usually
+/// authorized to use this digest. Keystore can only use PSS padding if it
+/// calculates the digest itself, so whether `RsaPkcsPss` can sign depends on
+/// the backend, see `can_sign`. It can verify with any backend.
+const RSA_MECHANISMS: &[MechanismType] = &[
+    MechanismType::RsaPkcs,
+    MechanismType::RsaPkcsPss,
+    MechanismType::Sha1RsaPkcs,
+    MechanismType::Sha256RsaPkcs,
+    MechanismType::Sha384RsaPkcs,
+    MechanismType::Sha512RsaPkcs,
+    MechanismType::Sha1RsaPkcsPss,
+    MechanismType::Sha256RsaPkcsPss,
+    MechanismType::Sha384RsaPkcsPss,
+    MechanismType::Sha512RsaPkcsPss,
+];
+
+/// Mechanisms that can be used with EC keys.
+const EC_MECHANISMS: &[MechanismType] = &[
+    MechanismType::Ecdsa,
+    MechanismType::EcdsaSha1,
+    MechanismType::EcdsaSha256,
+    MechanismType::EcdsaSha384,
+    MechanismType::EcdsaSha512,
+];
+
+/// Returns the mechanisms that can be used with the given key.
+pub fn for_key(key: &Key) -> &'static [MechanismType] {
+    match key {
+        Key::Rsa(_) => RSA_MECHANISMS,
+        Key::Ec(_) => EC_MECHANISMS,
+    }
+}
+
+/// Returns true if the mechanism can be used with at least one kind of key.
+pub fn is_supported(mechanism: MechanismType) -> bool {
+    RSA_MECHANISMS.contains(&mechanism) || EC_MECHANISMS.contains(&mechanism)
+}
+
+/// Returns the keystore signature algorithm that implements the given mechanism.
+/// Returns `None` if the mechanism is not supported, and for `RsaPkcsPss`, whose
+/// algorithm depends on its parameters, see `pss_algorithm`.
+pub fn algorithm(mechanism: MechanismType) -> Option<&'static str> {
+    match mechanism {
+        MechanismType::RsaPkcs => Some("NONEwithRSA"),
+        MechanismType::Sha1RsaPkcs => Some("SHA1withRSA"),
+        MechanismType::Sha256RsaPkcs => Some("SHA256withRSA"),
+        MechanismType::Sha384RsaPkcs => Some("SHA384withRSA"),
+        MechanismType::Sha512RsaPkcs => Some("SHA512withRSA"),
+        MechanismType::Sha1RsaPkcsPss => Some("SHA1withRSA/PSS"),
+        MechanismType::Sha256RsaPkcsPss => Some("SHA256withRSA/PSS"),
+        MechanismType::Sha384RsaPkcsPss => Some("SHA384withRSA/PSS"),
+        MechanismType::Sha512RsaPkcsPss => Some("SHA512withRSA/PSS"),
+        MechanismType::Ecdsa => Some("NONEwithECDSA"),
+        MechanismType::EcdsaSha1 => Some("SHA1withECDSA"),
+        MechanismType::EcdsaSha256 => Some("SHA256withECDSA"),
+        MechanismType::EcdsaSha384 => Some("SHA384withECDSA"),
+        MechanismType::EcdsaSha512 => Some("SHA512withECDSA"),
+        _ => None,
+    }
+}
+
+/// Returns the mechanisms that the backend can sign with, using the given key.
+pub fn for_signing<'a>(
+    key: &Key,
+    backend: &'a dyn Backend,
+) -> impl Iterator<Item = MechanismType> + 'a {
+    for_key(key)
+        .iter()
+        .copied()
+        .filter(move |&mechanism| can_sign(mechanism, backend))
+}
+
+/// Returns true if the backend can sign with the mechanism. `RsaPkcsPss` can sign
+/// if the backend can sign a hash made by any of the digests it accepts.
+pub fn can_sign(mechanism: MechanismType, backend: &dyn Backend) -> bool {
+    match mechanism {
+        MechanismType::RsaPkcsPss => [
+            MechanismType::Sha1,
+            MechanismType::Sha256,
+            MechanismType::Sha384,
+            MechanismType::Sha512,
+        ]
+        .iter()
+        .filter_map(|&digest| prehashed_pss(digest))
+        .any(|(_, algorithm)| backend.can_sign(algorithm)),
+        _ => algorithm(mechanism).is_some_and(|algorithm| backend.can_sign(algorithm)),
+    }
+}
+
+/// Returns true if the mechanism uses PSS padding, and therefore requires
+/// `RsaPkcsPssParams` as its parameter.
+pub fn is_pss(mechanism: MechanismType) -> bool {
+    mechanism == MechanismType::RsaPkcsPss || pss_digest(mechanism).is_some()
+}
+
+/// Checks the parameters of a PSS mechanism, and returns the keystore signature
+/// algorithm that implements it with these parameters. Keystore always uses MGF1
+/// with the same digest as the signature, and a salt that is as long as the digest.
+/// Only the parameters that match this behaviour are accepted. `RsaPkcsPss` signs
+/// a hash calculated by the application, which can be made by any of the digests
+/// of the other PSS mechanisms.
+pub fn pss_algorithm(mechanism: MechanismType, params: &RsaPkcsPssParams) -> Option<&'static str> {
+    let hash = MechanismType::try_from(params.hash_alg).ok()?;
+    let (signed, algorithm) = match mechanism {
+        MechanismType::RsaPkcsPss => prehashed_pss(hash)?,
+        _ => (mechanism, algorithm(mechanism)?),
+    };
+    let (digest, mgf, len) = pss_digest(signed)?;
+    let valid = hash == digest
+        && MaskGenerationFunction::try_from(params.mgf) == Ok(mgf)
+        && params.len == len;
+    valid.then_some(algorithm)
+}
+
+/// Returns the hash-and-sign PSS mechanism that uses the given digest, and the
+/// algorithm that signs a hash made by this digest.
+fn prehashed_pss(digest: MechanismType) -> Option<(MechanismType, &'static str)> {
+    match digest {
+        MechanismType::Sha1 => Some((MechanismType::Sha1RsaPkcsPss, "NONEwithRSA/PSS/SHA1")),
+        MechanismType::Sha256 => Some((MechanismType::Sha256RsaPkcsPss, "NONEwithRSA/PSS/SHA256")),
+        MechanismType::Sha384 => Some((MechanismType::Sha384RsaPkcsPss, "NONEwithRSA/PSS/SHA384")),
+        MechanismType::Sha512 => Some((MechanismType::Sha512RsaPkcsPss, "NONEwithRSA/PSS/SHA512")),
+        _ => None,
+    }
+}
+
+/// Returns the digest mechanism, the mask generation function and the digest
+/// length that is used by a PSS mechanism.
+fn pss_digest(
+    mechanism: MechanismType,
+) -> Option<(MechanismType, MaskGenerationFunction, c_ulong)> {
+    match mechanism {
+        MechanismType::Sha1RsaPkcsPss => {
+            Some((MechanismType::Sha1, MaskGenerationFunction::Mgf1Sha1, 20))
+        }
+        MechanismType::Sha256RsaPkcsPss => Some((
+            MechanismType::Sha256,
+            MaskGenerationFunction::Mgf1Sha256,
+            32,
+        )),
+        MechanismType::Sha384RsaPkcsPss => Some((
+            MechanismType::Sha384,
+            MaskGenerationFunction::Mgf1Sha384,
+            48,
+        )),
+        MechanismType::Sha512RsaPkcsPss => Some((
+            MechanismType::Sha512,
+            MaskGenerationFunction::Mgf1Sha512,
+            64,
+        )),
+        _ => None,
+    }
+}
+
+/// Returns the mechanisms that can be used with at least one of the given keys.
+/// Each mechanism is listed only once.
+pub fn list<'a>(keys: impl IntoIterator<Item = &'a Key>) -> Vec<MechanismType> {
+    let mut mechanisms = Vec::new();
+    for mechanism in keys.into_iter().flat_map(for_key) {
+        if !mechanisms.contains(mechanism) {
+            mechanisms.push(*mechanism);
+        }
+    }
+    mechanisms
+}
+
+/// Returns the details of a mechanism, with the key sizes limited to the sizes
+/// of the given keys. Returns `None` if none of the keys support this mechanism.
+/// The mechanism is only reported to sign if the backend can sign with it.
+pub fn info<'a>(
+    mechanism: MechanismType,
+    keys: impl IntoIterator<Item = &'a Key>,
+    backend: &dyn Backend,
+) -> Option<MechanismInfo> {
+    let sizes: Vec<usize> = keys
+        .into_iter()
+        .filter(|key| for_key(key).contains(&mechanism))
+        .map(Key::size)
+        .collect();
+    let min_key_size = *sizes.iter().min()?;
+    let max_key_size = *sizes.iter().max()?;
+
+    let mut flags = Flags::VERIFY | Flags::HW;
+    if can_sign(mechanism, backend) {
+        flags |= Flags::SIGN;
+    }
+    if EC_MECHANISMS.contains(&mechanism) {
+        flags |= Flags::EC_F_P | Flags::EC_NAMEDCURVE | Flags::EC_UNCOMPRESS;
+    }
+    Some(MechanismInfo {
+        min_key_size: min_key_size.try_into().ok()?,
+        max_key_size: max_key_size.try_into().ok()?,
+        flags: flags.bits().into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithm::Algorithm;
+    use crate::bridge::Cancellation;
+    use crate::error::Error;
+
+    /// A backend that can only tell which algorithms it signs with.
+    struct Signer(fn(&str) -> bool);
+
+    impl Backend for Signer {
+        fn list_keys(&self) -> Result<String, Error> {
+            unimplemented!()
+        }
+
+        fn can_sign(&self, algorithm: &str) -> bool {
+            (self.0)(algorithm)
+        }
+
+        fn sign(&self, _: &str, _: &str, _: &[u8], _: &Cancellation) -> Result<Vec<u8>, Error> {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn prehashed_pss_needs_a_backend_that_signs_hashes() {
+        // Like the termux backend, which cannot sign a hash with PSS padding.
+        let keystore = Signer(|algorithm| {
+            Algorithm::from_name(algorithm).is_some_and(|algorithm| algorithm.input_len().is_none())
+        });
+        assert!(!can_sign(MechanismType::RsaPkcsPss, &keystore));
+        assert!(can_sign(MechanismType::Sha256RsaPkcsPss, &keystore));
+        assert!(can_sign(MechanismType::RsaPkcs, &keystore));
+        assert!(can_sign(MechanismType::Ecdsa, &keystore));
+
+        let software = Signer(|algorithm| Algorithm::from_name(algorithm).is_some());
+        assert!(can_sign(MechanismType::RsaPkcsPss, &software));
+    }
+
+    #[test]
+    fn unsupported_mechanisms_cannot_sign() {
+        let any = Signer(|_| true);
+        assert!(can_sign(MechanismType::RsaPkcsPss, &any));
+        assert!(!can_sign(MechanismType::Sha256, &any));
+        assert!(!can_sign(MechanismType::RsaPkcsKeyPairGen, &any));
+    }
+}
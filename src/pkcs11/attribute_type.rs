@@ -4,7 +4,10 @@ use std::os::raw::c_ulong;
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive, ToPrimitive};
 
-#[derive(Debug, FromPrimitive, ToPrimitive)]
+use super::{u64_to_ulong, ulong_to_u64};
+
+#[derive(Debug, Clone, Copy, PartialEq, FromPrimitive, ToPrimitive)]
+#[repr(u32)]
 pub enum AttributeType {
     Class = 0x0000,
     Token = 0x0001,
@@ -117,13 +120,15 @@ pub enum AttributeType {
 impl TryFrom<c_ulong> for AttributeType {
     type Error = ();
     fn try_from(value: c_ulong) -> Result<Self, Self::Error> {
-        AttributeType::from_u64(value).ok_or(())
+        AttributeType::from_u64(ulong_to_u64(value)).ok_or(())
     }
 }
 
 impl TryFrom<AttributeType> for c_ulong {
     type Error = ();
     fn try_from(value: AttributeType) -> Result<Self, Self::Error> {
-        AttributeType::to_u64(&value).ok_or(())
+        AttributeType::to_u64(&value)
+            .and_then(u64_to_ulong)
+            .ok_or(())
     }
 }
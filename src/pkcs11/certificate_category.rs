@@ -4,6 +4,8 @@ use std::os::raw::c_ulong;
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive, ToPrimitive};
 
+use super::{u64_to_ulong, ulong_to_u64};
+
 #[derive(Debug, FromPrimitive, ToPrimitive)]
 pub enum CertificateCategory {
     Unspecified = 0,
@@ -15,13 +17,15 @@ pub enum CertificateCategory {
 impl TryFrom<c_ulong> for CertificateCategory {
     type Error = ();
     fn try_from(value: c_ulong) -> Result<Self, Self::Error> {
-        CertificateCategory::from_u64(value).ok_or(())
+        CertificateCategory::from_u64(ulong_to_u64(value)).ok_or(())
     }
 }
 
 impl TryFrom<CertificateCategory> for c_ulong {
     type Error = ();
     fn try_from(value: CertificateCategory) -> Result<Self, Self::Error> {
-        CertificateCategory::to_u64(&value).ok_or(())
+        CertificateCategory::to_u64(&value)
+            .and_then(u64_to_ulong)
+            .ok_or(())
     }
 }
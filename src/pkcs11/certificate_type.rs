@@ -4,7 +4,10 @@ use std::os::raw::c_ulong;
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive, ToPrimitive};
 
+use super::{u64_to_ulong, ulong_to_u64};
+
 #[derive(Debug, FromPrimitive, ToPrimitive)]
+#[repr(u32)]
 pub enum CertificateType {
     X509 = 0,
     X509AttrCert = 1,
@@ -15,13 +18,15 @@ pub enum CertificateType {
 impl TryFrom<c_ulong> for CertificateType {
     type Error = ();
     fn try_from(value: c_ulong) -> Result<Self, Self::Error> {
-        CertificateType::from_u64(value).ok_or(())
+        CertificateType::from_u64(ulong_to_u64(value)).ok_or(())
     }
 }
 
 impl TryFrom<CertificateType> for c_ulong {
     type Error = ();
     fn try_from(value: CertificateType) -> Result<Self, Self::Error> {
-        CertificateType::to_u64(&value).ok_or(())
+        CertificateType::to_u64(&value)
+            .and_then(u64_to_ulong)
+            .ok_or(())
     }
 }
@@ -4,7 +4,10 @@ use std::os::raw::c_ulong;
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive, ToPrimitive};
 
+use super::{u64_to_ulong, ulong_to_u64};
+
 #[derive(Debug, FromPrimitive, ToPrimitive)]
+#[repr(u32)]
 pub enum HardwareFeatureType {
     MonotonicCounter = 0x01,
     Clock = 0x02,
@@ -15,13 +18,15 @@ pub enum HardwareFeatureType {
 impl TryFrom<c_ulong> for HardwareFeatureType {
     type Error = ();
     fn try_from(value: c_ulong) -> Result<Self, Self::Error> {
-        HardwareFeatureType::from_u64(value).ok_or(())
+        HardwareFeatureType::from_u64(ulong_to_u64(value)).ok_or(())
     }
 }
 
 impl TryFrom<HardwareFeatureType> for c_ulong {
     type Error = ();
     fn try_from(value: HardwareFeatureType) -> Result<Self, Self::Error> {
-        HardwareFeatureType::to_u64(&value).ok_or(())
+        HardwareFeatureType::to_u64(&value)
+            .and_then(u64_to_ulong)
+            .ok_or(())
     }
 }
@@ -4,6 +4,8 @@ use std::os::raw::c_ulong;
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive, ToPrimitive};
 
+use super::{u64_to_ulong, ulong_to_u64};
+
 #[derive(Debug, FromPrimitive, ToPrimitive)]
 pub enum KeyDerivationFunction {
     Null = 1,
@@ -20,13 +22,15 @@ pub enum KeyDerivationFunction {
 impl TryFrom<c_ulong> for KeyDerivationFunction {
     type Error = ();
     fn try_from(value: c_ulong) -> Result<Self, Self::Error> {
-        KeyDerivationFunction::from_u64(value).ok_or(())
+        KeyDerivationFunction::from_u64(ulong_to_u64(value)).ok_or(())
     }
 }
 
 impl TryFrom<KeyDerivationFunction> for c_ulong {
     type Error = ();
     fn try_from(value: KeyDerivationFunction) -> Result<Self, Self::Error> {
-        KeyDerivationFunction::to_u64(&value).ok_or(())
+        KeyDerivationFunction::to_u64(&value)
+            .and_then(u64_to_ulong)
+            .ok_or(())
     }
 }
@@ -4,7 +4,10 @@ use std::os::raw::c_ulong;
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive, ToPrimitive};
 
+use super::{u64_to_ulong, ulong_to_u64};
+
 #[derive(Debug, FromPrimitive, ToPrimitive)]
+#[repr(u32)]
 pub enum KeyType {
     Rsa = 0x0000,
     Dsa = 0x0001,
@@ -53,13 +56,13 @@ pub enum KeyType {
 impl TryFrom<c_ulong> for KeyType {
     type Error = ();
     fn try_from(value: c_ulong) -> Result<Self, Self::Error> {
-        KeyType::from_u64(value).ok_or(())
+        KeyType::from_u64(ulong_to_u64(value)).ok_or(())
     }
 }
 
 impl TryFrom<KeyType> for c_ulong {
     type Error = ();
     fn try_from(value: KeyType) -> Result<Self, Self::Error> {
-        KeyType::to_u64(&value).ok_or(())
+        KeyType::to_u64(&value).and_then(u64_to_ulong).ok_or(())
     }
 }
@@ -4,7 +4,9 @@ use std::os::raw::c_ulong;
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive, ToPrimitive};
 
-#[derive(Debug, FromPrimitive, ToPrimitive)]
+use super::{u64_to_ulong, ulong_to_u64};
+
+#[derive(Debug, Clone, Copy, PartialEq, FromPrimitive, ToPrimitive)]
 pub enum MaskGenerationFunction {
     Mgf1Sha1 = 1,
     Mgf1Sha256 = 2,
@@ -16,13 +18,15 @@ pub enum MaskGenerationFunction {
 impl TryFrom<c_ulong> for MaskGenerationFunction {
     type Error = ();
     fn try_from(value: c_ulong) -> Result<Self, Self::Error> {
-        MaskGenerationFunction::from_u64(value).ok_or(())
+        MaskGenerationFunction::from_u64(ulong_to_u64(value)).ok_or(())
     }
 }
 
 impl TryFrom<MaskGenerationFunction> for c_ulong {
     type Error = ();
     fn try_from(value: MaskGenerationFunction) -> Result<Self, Self::Error> {
-        MaskGenerationFunction::to_u64(&value).ok_or(())
+        MaskGenerationFunction::to_u64(&value)
+            .and_then(u64_to_ulong)
+            .ok_or(())
     }
 }
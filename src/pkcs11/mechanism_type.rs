@@ -4,7 +4,10 @@ use std::os::raw::c_ulong;
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive, ToPrimitive};
 
-#[derive(Debug, FromPrimitive, ToPrimitive)]
+use super::{u64_to_ulong, ulong_to_u64};
+
+#[derive(Debug, Clone, Copy, PartialEq, FromPrimitive, ToPrimitive)]
+#[repr(u32)]
 pub enum MechanismType {
     RsaPkcsKeyPairGen = 0x0000,
     RsaPkcs = 0x0001,
@@ -335,13 +338,15 @@ pub enum MechanismType {
 impl TryFrom<c_ulong> for MechanismType {
     type Error = ();
     fn try_from(value: c_ulong) -> Result<Self, Self::Error> {
-        MechanismType::from_u64(value).ok_or(())
+        MechanismType::from_u64(ulong_to_u64(value)).ok_or(())
     }
 }
 
 impl TryFrom<MechanismType> for c_ulong {
     type Error = ();
     fn try_from(value: MechanismType) -> Result<Self, Self::Error> {
-        MechanismType::to_u64(&value).ok_or(())
+        MechanismType::to_u64(&value)
+            .and_then(u64_to_ulong)
+            .ok_or(())
     }
 }
@@ -3,13 +3,14 @@
 //! however it is now heavily modified.
 
 use std::convert::TryInto;
-use std::slice;
+use std::os::raw::c_ulong;
+use std::{mem, slice};
 
 pub const CRYPTOKI_VERSION_MAJOR: u32 = 2;
 pub const CRYPTOKI_VERSION_MINOR: u32 = 40;
 pub const _CRYPTOKI_VERSION_AMENDMENT: u32 = 0;
 pub const UNAVAILABLE_INFORMATION: i32 = -1;
-pub const _EFFECTIVELY_INFINITE: u32 = 0;
+pub const EFFECTIVELY_INFINITE: u32 = 0;
 pub const _INVALID_HANDLE: u32 = 0;
 pub const _NEED_ARG_LIST: u32 = 1;
 //pub const CKZ_DATA_SPECIFIED: u32 = 1;
@@ -52,20 +53,29 @@ mod types;
 pub use types::*;
 
 impl Attribute {
-    /// Sets the value field of this attribute. Returns `None`
-    /// if the attribute does not have enough space to store this value.
+    /// Sets the value field of this attribute. If the value field is null, only the
+    /// length of the value is set so that the application can allocate a buffer for it.
+    /// Returns `None` if the attribute does not have enough space to store this value,
+    /// in which case the value is left untouched and the length is set to unavailable.
     ///
     /// # Safety
     /// As attribute does not own the value field (instead it contains a pointer)
     /// any safety considerations regarding pointers apply.
     pub fn set_value(&mut self, value: &[u8]) -> Option<()> {
-        let len: u64 = value.len().try_into().ok()?;
+        let len = match value.len().try_into() {
+            Ok(len) => len,
+            Err(_) => {
+                self.set_unavailable();
+                return None;
+            }
+        };
         if self.value.is_null() {
             self.value_len = len;
             return Some(());
         }
 
         if self.value_len < len {
+            self.set_unavailable();
             return None;
         }
 
@@ -76,20 +86,44 @@ impl Attribute {
         Some(())
     }
 
-    /// Sets the value of this attribute to the given value. Use `set_value` if you
-    /// need to set a slice instead.
+    /// Marks the value of this attribute as unavailable, which is used when the value
+    /// cannot be returned for any reason. The value field is not touched.
+    pub fn set_unavailable(&mut self) {
+        self.value_len = UNAVAILABLE_INFORMATION as c_ulong;
+    }
+}
+
+impl Mechanism {
+    /// Returns the parameter of this mechanism as the given type. Returns `None`
+    /// if there is no parameter or if its length does not match the type.
     ///
     /// # Safety
-    /// Attribute values are pointers, therefore any safety considerations
-    /// regarding pointers apply.
-    pub fn set_value_single(&mut self, value: u64) {
-        let val = self.value as *mut u64;
-        unsafe {
-            *val = value;
+    /// As mechanism does not own the parameter field (instead it contains a pointer)
+    /// any safety considerations regarding pointers apply. The parameter must be a
+    /// valid `T` if its length matches, and must outlive the returned reference.
+    pub unsafe fn parameter<T>(&self) -> Option<&T> {
+        let len: usize = self.parameter_len.try_into().ok()?;
+        if self.parameter.is_null() || len != mem::size_of::<T>() {
+            return None;
         }
+        Some(unsafe { &*(self.parameter as *const T) })
     }
 }
 
+/// Converts a `CK_ULONG` value to `u64`. `CK_ULONG` is 32 bits wide on 32-bit targets
+/// and 64 bits wide on 64-bit targets, so this conversion never fails.
+#[allow(clippy::useless_conversion)]
+pub fn ulong_to_u64(value: c_ulong) -> u64 {
+    value.into()
+}
+
+/// Converts a `u64` value to `CK_ULONG`. Returns `None` if the value does not fit,
+/// which can only happen on 32-bit targets.
+#[allow(clippy::useless_conversion)]
+pub fn u64_to_ulong(value: u64) -> Option<c_ulong> {
+    value.try_into().ok()
+}
+
 /// Copy a string to the target slice. If the slice is larger than the size required
 /// by the string, the rest is filled with empty space.
 pub fn copy_padded(target: &mut [u8], data: &str) {
@@ -100,3 +134,120 @@ pub fn copy_padded(target: &mut [u8], data: &str) {
     }
     left.copy_from_slice(data);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The largest `CK_ULONG` value, as expected on each target.
+    #[cfg(target_pointer_width = "32")]
+    const ULONG_MAX: u64 = 0xffff_ffff;
+    #[cfg(target_pointer_width = "64")]
+    const ULONG_MAX: u64 = u64::MAX;
+
+    /// Returns an attribute whose value field points to the given buffer.
+    fn attribute_with(buf: &mut [u8]) -> Attribute {
+        Attribute {
+            type_: 0,
+            value: buf.as_mut_ptr().cast(),
+            value_len: buf.len().try_into().unwrap(),
+        }
+    }
+
+    #[test]
+    fn ulong_has_target_width() {
+        assert_eq!(mem::size_of::<c_ulong>(), mem::size_of::<usize>());
+    }
+
+    #[test]
+    fn ulong_to_u64_keeps_the_value() {
+        assert_eq!(ulong_to_u64(0), 0);
+        assert_eq!(ulong_to_u64(c_ulong::MAX), ULONG_MAX);
+    }
+
+    #[test]
+    fn u64_to_ulong_checks_the_bounds() {
+        assert_eq!(u64_to_ulong(0), Some(0));
+        assert_eq!(u64_to_ulong(ULONG_MAX), Some(c_ulong::MAX));
+        #[cfg(target_pointer_width = "32")]
+        {
+            assert_eq!(u64_to_ulong(ULONG_MAX + 1), None);
+            assert_eq!(u64_to_ulong(u64::MAX), None);
+        }
+        #[cfg(target_pointer_width = "64")]
+        assert_eq!(u64_to_ulong(u64::MAX), Some(c_ulong::MAX));
+    }
+
+    #[test]
+    fn unavailable_information_is_all_ones() {
+        let mut attribute = attribute_with(&mut []);
+        attribute.set_unavailable();
+        assert_eq!(attribute.value_len, c_ulong::MAX);
+    }
+
+    #[test]
+    fn set_value_without_buffer_reports_length() {
+        let mut attribute = Attribute {
+            type_: 0,
+            value: std::ptr::null_mut(),
+            value_len: 0,
+        };
+        assert_eq!(attribute.set_value(&[1, 2, 3]), Some(()));
+        assert_eq!(attribute.value_len, 3);
+    }
+
+    #[test]
+    fn set_value_copies_into_large_enough_buffer() {
+        let mut buf = [0xff; 8];
+        let mut attribute = attribute_with(&mut buf);
+        assert_eq!(attribute.set_value(&[1, 2, 3]), Some(()));
+        assert_eq!(attribute.value_len, 3);
+        assert_eq!(buf, [1, 2, 3, 0xff, 0xff, 0xff, 0xff, 0xff]);
+
+        let mut buf = [0xff; 3];
+        let mut attribute = attribute_with(&mut buf);
+        assert_eq!(attribute.set_value(&[4, 5, 6]), Some(()));
+        assert_eq!(attribute.value_len, 3);
+        assert_eq!(buf, [4, 5, 6]);
+    }
+
+    #[test]
+    fn set_value_into_small_buffer_is_unavailable() {
+        let mut buf = [0xff; 2];
+        let mut attribute = attribute_with(&mut buf);
+        assert_eq!(attribute.set_value(&[1, 2, 3]), None);
+        assert_eq!(attribute.value_len, UNAVAILABLE_INFORMATION as c_ulong);
+        assert_eq!(buf, [0xff, 0xff]);
+    }
+
+    #[test]
+    fn set_value_accepts_empty_value() {
+        let mut buf = [0xff; 2];
+        let mut attribute = attribute_with(&mut buf);
+        assert_eq!(attribute.set_value(&[]), Some(()));
+        assert_eq!(attribute.value_len, 0);
+        assert_eq!(buf, [0xff, 0xff]);
+    }
+
+    #[test]
+    fn parameter_checks_length() {
+        let mut params = RsaPkcsPssParams {
+            hash_alg: 1,
+            mgf: 2,
+            len: 3,
+        };
+        let mut mechanism = Mechanism {
+            mechanism: 0,
+            parameter: (&mut params as *mut RsaPkcsPssParams).cast(),
+            parameter_len: mem::size_of::<RsaPkcsPssParams>().try_into().unwrap(),
+        };
+        let parameter = unsafe { mechanism.parameter::<RsaPkcsPssParams>() };
+        assert_eq!(parameter.map(|params| params.len), Some(3));
+
+        mechanism.parameter_len -= 1;
+        assert!(unsafe { mechanism.parameter::<RsaPkcsPssParams>() }.is_none());
+        mechanism.parameter = std::ptr::null_mut();
+        mechanism.parameter_len += 1;
+        assert!(unsafe { mechanism.parameter::<RsaPkcsPssParams>() }.is_none());
+    }
+}
@@ -4,6 +4,8 @@ use std::os::raw::c_ulong;
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive, ToPrimitive};
 
+use super::{u64_to_ulong, ulong_to_u64};
+
 #[derive(Debug, FromPrimitive, ToPrimitive)]
 pub enum Notification {
     Surrender = 0,
@@ -13,13 +15,15 @@ pub enum Notification {
 impl TryFrom<c_ulong> for Notification {
     type Error = ();
     fn try_from(value: c_ulong) -> Result<Self, Self::Error> {
-        Notification::from_u64(value).ok_or(())
+        Notification::from_u64(ulong_to_u64(value)).ok_or(())
     }
 }
 
 impl TryFrom<Notification> for c_ulong {
     type Error = ();
     fn try_from(value: Notification) -> Result<Self, Self::Error> {
-        Notification::to_u64(&value).ok_or(())
+        Notification::to_u64(&value)
+            .and_then(u64_to_ulong)
+            .ok_or(())
     }
 }
@@ -4,7 +4,10 @@ use std::os::raw::c_ulong;
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive, ToPrimitive};
 
-#[derive(Debug, FromPrimitive, ToPrimitive)]
+use super::{u64_to_ulong, ulong_to_u64};
+
+#[derive(Debug, Clone, Copy, PartialEq, FromPrimitive, ToPrimitive)]
+#[repr(u32)]
 pub enum ObjectClass {
     Data = 0,
     Certificate = 1,
@@ -21,13 +24,13 @@ pub enum ObjectClass {
 impl TryFrom<c_ulong> for ObjectClass {
     type Error = ();
     fn try_from(value: c_ulong) -> Result<Self, Self::Error> {
-        ObjectClass::from_u64(value).ok_or(())
+        ObjectClass::from_u64(ulong_to_u64(value)).ok_or(())
     }
 }
 
 impl TryFrom<ObjectClass> for c_ulong {
     type Error = ();
     fn try_from(value: ObjectClass) -> Result<Self, Self::Error> {
-        ObjectClass::to_u64(&value).ok_or(())
+        ObjectClass::to_u64(&value).and_then(u64_to_ulong).ok_or(())
     }
 }
@@ -4,6 +4,8 @@ use std::os::raw::c_ulong;
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive, ToPrimitive};
 
+use super::{u64_to_ulong, ulong_to_u64};
+
 pub const _VALUE: u32 = 0;
 pub const _PIN: u32 = 1;
 pub const _CHALLENGE: u32 = 2;
@@ -31,27 +33,27 @@ pub enum Param {
 impl TryFrom<c_ulong> for Format {
     type Error = ();
     fn try_from(value: c_ulong) -> Result<Self, Self::Error> {
-        Format::from_u64(value).ok_or(())
+        Format::from_u64(ulong_to_u64(value)).ok_or(())
     }
 }
 
 impl TryFrom<Format> for c_ulong {
     type Error = ();
     fn try_from(value: Format) -> Result<Self, Self::Error> {
-        Format::to_u64(&value).ok_or(())
+        Format::to_u64(&value).and_then(u64_to_ulong).ok_or(())
     }
 }
 
 impl TryFrom<c_ulong> for Param {
     type Error = ();
     fn try_from(value: c_ulong) -> Result<Self, Self::Error> {
-        Param::from_u64(value).ok_or(())
+        Param::from_u64(ulong_to_u64(value)).ok_or(())
     }
 }
 
 impl TryFrom<Param> for c_ulong {
     type Error = ();
     fn try_from(value: Param) -> Result<Self, Self::Error> {
-        Param::to_u64(&value).ok_or(())
+        Param::to_u64(&value).and_then(u64_to_ulong).ok_or(())
     }
 }
@@ -4,6 +4,8 @@ use std::os::raw::c_ulong;
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive, ToPrimitive};
 
+use super::{u64_to_ulong, ulong_to_u64};
+
 #[derive(Debug, FromPrimitive, ToPrimitive)]
 pub enum PseudoRandomFunction {
     Pkcs5Pbkd2HmacSha1 = 1,
@@ -19,13 +21,15 @@ pub enum PseudoRandomFunction {
 impl TryFrom<c_ulong> for PseudoRandomFunction {
     type Error = ();
     fn try_from(value: c_ulong) -> Result<Self, Self::Error> {
-        PseudoRandomFunction::from_u64(value).ok_or(())
+        PseudoRandomFunction::from_u64(ulong_to_u64(value)).ok_or(())
     }
 }
 
 impl TryFrom<PseudoRandomFunction> for c_ulong {
     type Error = ();
     fn try_from(value: PseudoRandomFunction) -> Result<Self, Self::Error> {
-        PseudoRandomFunction::to_u64(&value).ok_or(())
+        PseudoRandomFunction::to_u64(&value)
+            .and_then(u64_to_ulong)
+            .ok_or(())
     }
 }
@@ -4,7 +4,10 @@ use std::os::raw::c_ulong;
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive, ToPrimitive};
 
+use super::{u64_to_ulong, ulong_to_u64};
+
 #[derive(Debug, FromPrimitive, ToPrimitive)]
+#[repr(u32)]
 pub enum ReturnValue {
     Ok = 0x0000,
     Cancel = 0x0001,
@@ -106,13 +109,13 @@ pub enum ReturnValue {
 impl TryFrom<c_ulong> for ReturnValue {
     type Error = ();
     fn try_from(value: c_ulong) -> Result<Self, Self::Error> {
-        ReturnValue::from_u64(value).ok_or(())
+        ReturnValue::from_u64(ulong_to_u64(value)).ok_or(())
     }
 }
 
 impl TryFrom<ReturnValue> for c_ulong {
     type Error = ();
     fn try_from(value: ReturnValue) -> Result<Self, Self::Error> {
-        ReturnValue::to_u64(&value).ok_or(())
+        ReturnValue::to_u64(&value).and_then(u64_to_ulong).ok_or(())
     }
 }
@@ -4,6 +4,8 @@ use std::os::raw::c_ulong;
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive, ToPrimitive};
 
+use super::{u64_to_ulong, ulong_to_u64};
+
 #[derive(Debug, FromPrimitive, ToPrimitive)]
 pub enum SecurityDomain {
     Unspecified = 0,
@@ -15,13 +17,15 @@ pub enum SecurityDomain {
 impl TryFrom<c_ulong> for SecurityDomain {
     type Error = ();
     fn try_from(value: c_ulong) -> Result<Self, Self::Error> {
-        SecurityDomain::from_u64(value).ok_or(())
+        SecurityDomain::from_u64(ulong_to_u64(value)).ok_or(())
     }
 }
 
 impl TryFrom<SecurityDomain> for c_ulong {
     type Error = ();
     fn try_from(value: SecurityDomain) -> Result<Self, Self::Error> {
-        SecurityDomain::to_u64(&value).ok_or(())
+        SecurityDomain::to_u64(&value)
+            .and_then(u64_to_ulong)
+            .ok_or(())
     }
 }
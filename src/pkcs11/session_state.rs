@@ -4,7 +4,9 @@ use std::os::raw::c_ulong;
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive, ToPrimitive};
 
-#[derive(Debug, FromPrimitive, ToPrimitive)]
+use super::{u64_to_ulong, ulong_to_u64};
+
+#[derive(Debug, Clone, Copy, PartialEq, FromPrimitive, ToPrimitive)]
 pub enum SessionState {
     RoPublicSession = 0,
     RoUserFunctions = 1,
@@ -16,13 +18,15 @@ pub enum SessionState {
 impl TryFrom<c_ulong> for SessionState {
     type Error = ();
     fn try_from(value: c_ulong) -> Result<Self, Self::Error> {
-        SessionState::from_u64(value).ok_or(())
+        SessionState::from_u64(ulong_to_u64(value)).ok_or(())
     }
 }
 
 impl TryFrom<SessionState> for c_ulong {
     type Error = ();
     fn try_from(value: SessionState) -> Result<Self, Self::Error> {
-        SessionState::to_u64(&value).ok_or(())
+        SessionState::to_u64(&value)
+            .and_then(u64_to_ulong)
+            .ok_or(())
     }
 }
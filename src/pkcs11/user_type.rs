@@ -4,6 +4,8 @@ use std::os::raw::c_ulong;
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive, ToPrimitive};
 
+use super::{u64_to_ulong, ulong_to_u64};
+
 #[derive(Debug, FromPrimitive, ToPrimitive)]
 pub enum UserType {
     So = 0,
@@ -14,13 +16,13 @@ pub enum UserType {
 impl TryFrom<c_ulong> for UserType {
     type Error = ();
     fn try_from(value: c_ulong) -> Result<Self, Self::Error> {
-        UserType::from_u64(value).ok_or(())
+        UserType::from_u64(ulong_to_u64(value)).ok_or(())
     }
 }
 
 impl TryFrom<UserType> for c_ulong {
     type Error = ();
     fn try_from(value: UserType) -> Result<Self, Self::Error> {
-        UserType::to_u64(&value).ok_or(())
+        UserType::to_u64(&value).and_then(u64_to_ulong).ok_or(())
     }
 }
@@ -0,0 +1,155 @@
+//! Holds the keys that are shared by all the sessions. Object handles are derived from
+//! the position of the keys in this inventory. A key keeps its position when the keys
+//! are fetched again, so the handles stay valid across sessions and refreshes.
+//!
+//! Fetching the keys takes a while with the termux keystore, so they are only fetched
+//! again once they are older than `TTL`, or once the inventory has been invalidated.
+//! Whatever changes the keys in the keystore must call `refresh` or `invalidate`
+//! afterwards. tergent does not generate or delete keys itself yet, `C_GenerateKeyPair`
+//! and `C_DestroyObject` are not supported, so this is only done when a key turns out
+//! to be missing.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::key::{self, Key};
+use crate::pkcs11::ReturnValue;
+
+/// How long the fetched keys are used before they are fetched again, so that the keys
+/// created or deleted outside of this library are noticed eventually.
+const TTL: Duration = Duration::from_secs(30);
+
+/// The keys that were fetched most recently. `None` if they have not been fetched yet.
+static INSTANCE: Mutex<Option<Inventory>> = Mutex::new(None);
+
+/// Serializes the fetches, so that concurrent fetches do not replace each other.
+/// It is only held while fetching, the inventory itself stays readable meanwhile.
+static FETCH: Mutex<()> = Mutex::new(());
+
+/// The keys in the inventory, along with when they were fetched.
+struct Inventory {
+    keys: Arc<Keys>,
+    /// When the keys started to be fetched. `None` if the inventory has been invalidated.
+    fetched: Option<Instant>,
+}
+
+/// A snapshot of the keys in the keystore, indexed by their positions. The positions
+/// of the keys that have been deleted since they were first fetched are left empty,
+/// so that their handles are not given to other keys.
+#[derive(Default)]
+pub struct Keys(Vec<Option<Key>>);
+
+impl Keys {
+    /// Returns the key at the given position, if there is one.
+    pub fn get(&self, index: usize) -> Option<&Key> {
+        self.0.get(index)?.as_ref()
+    }
+
+    /// Returns the keys along with their positions.
+    pub fn indexed(&self) -> impl DoubleEndedIterator<Item = (usize, &Key)> {
+        self.0
+            .iter()
+            .enumerate()
+            .filter_map(|(index, key)| Some((index, key.as_ref()?)))
+    }
+
+    /// Returns the keys without their positions.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Key> {
+        self.0.iter().flatten()
+    }
+
+    /// Places the keys that were just fetched at the positions they have in this
+    /// snapshot. A key is considered the same if it has the same label and the same
+    /// public key, the new keys are placed after all the others.
+    fn merge(&self, fetched: Vec<Key>) -> Keys {
+        let mut keys: Vec<Option<Key>> = self.0.iter().map(|_| None).collect();
+        for key in fetched {
+            let id = key.id();
+            let position = self.0.iter().position(|old| {
+                old.as_ref()
+                    .is_some_and(|old| old.label() == key.label() && old.id() == id)
+            });
+            match position {
+                Some(index) if keys[index].is_none() => keys[index] = Some(key),
+                _ => keys.push(Some(key)),
+            }
+        }
+        Keys(keys)
+    }
+}
+
+/// Returns the keys in the inventory, fetching them from the keystore backend if they
+/// have not been fetched yet, or if they are out of date.
+pub fn get() -> Result<Arc<Keys>, ReturnValue> {
+    match fresh(|fetched| fetched.elapsed() < TTL)? {
+        Some(keys) => Ok(keys),
+        None => refresh(),
+    }
+}
+
+/// Fetches the keys from the keystore backend right away, regardless of how old they
+/// are, and returns them. This is the hook to call once the keys in the keystore are
+/// known to have changed and the changes are needed immediately. The keys keep their
+/// positions, and the sessions that are already open keep using the keys they were
+/// opened with.
+pub fn refresh() -> Result<Arc<Keys>, ReturnValue> {
+    let requested = Instant::now();
+    let _fetch = lock(&FETCH)?;
+    // Another thread may have fetched the keys while this one was waiting. If that
+    // started after this call, the keys are just as recent as fetching them again.
+    if let Some(keys) = fresh(|fetched| fetched >= requested)? {
+        return Ok(keys);
+    }
+    fetch()
+}
+
+/// Marks the keys as out of date, so that they are fetched again when they are needed
+/// next, regardless of how old they are. This is the lazy alternative to `refresh`.
+/// Unlike `clear`, the keys keep their positions, and the sessions that are already
+/// open keep using the keys they were opened with.
+pub fn invalidate() {
+    if let Ok(mut inventory) = INSTANCE.lock() {
+        if let Some(inventory) = inventory.as_mut() {
+            inventory.fetched = None;
+        }
+    }
+}
+
+/// Empties the inventory, so that the keys are fetched again when they are needed next.
+pub fn clear() {
+    if let Ok(mut inventory) = INSTANCE.lock() {
+        *inventory = None;
+    }
+}
+
+/// Returns the keys in the inventory, unless they are missing, invalidated, or were
+/// fetched at a time that is not accepted by `accept`.
+fn fresh(accept: impl Fn(Instant) -> bool) -> Result<Option<Arc<Keys>>, ReturnValue> {
+    let inventory = lock(&INSTANCE)?;
+    let keys = inventory
+        .as_ref()
+        .filter(|inventory| inventory.fetched.is_some_and(&accept));
+    Ok(keys.map(|inventory| Arc::clone(&inventory.keys)))
+}
+
+/// Fetches the keys and stores them in the inventory. Must be called while holding `FETCH`.
+fn fetch() -> Result<Arc<Keys>, ReturnValue> {
+    let started = Instant::now();
+    let fetched = key::list().map_err(ReturnValue::from)?;
+    let mut inventory = lock(&INSTANCE)?;
+    let keys = match inventory.as_ref() {
+        Some(inventory) => inventory.keys.merge(fetched),
+        None => Keys(fetched.into_iter().map(Some).collect()),
+    };
+    let keys = Arc::new(keys);
+    *inventory = Some(Inventory {
+        keys: Arc::clone(&keys),
+        fetched: Some(started),
+    });
+    Ok(keys)
+}
+
+/// Locks one of the mutexes above, failing with `GeneralError` if it is poisoned.
+fn lock<T>(mutex: &Mutex<T>) -> Result<MutexGuard<'_, T>, ReturnValue> {
+    mutex.lock().map_err(|_| ReturnValue::GeneralError)
+}
@@ -1,79 +1,399 @@
 //! Provides functionality to store the library state.
 
-use crate::bridge;
-use crate::key::{self, Key};
+use std::convert::TryFrom;
+use std::mem;
+use std::os::raw::c_ulong;
+use std::sync::Arc;
 
+use crate::algorithm::Algorithm;
+use crate::backend::Backend;
+use crate::bridge::Cancellation;
+use crate::error::Error;
+use crate::key::Key;
+use crate::pkcs11::{AttributeType, Flags, ReturnValue, SessionState};
+
+pub mod inventory;
+use inventory::Keys;
+mod object;
+pub use object::Object;
 mod store;
 pub use store::*;
 
 /// Represents the state of the library. Each session should
 /// have a single state associated with it.
 pub struct State {
-    keys: Vec<Key>,
-    search_index: usize,
-    search_for_keys: bool,
-    search_id: Option<String>,
-    sign_index: usize,
+    slot_id: c_ulong,
+    flags: Flags,
+    session_state: SessionState,
+    keys: Arc<Keys>,
+    cancellation: Arc<Cancellation>,
+    search: Option<Vec<Object>>,
+    sign: Option<Operation>,
+    verify: Option<Operation>,
+}
+
+/// An active sign or verify operation.
+struct Operation {
+    /// Index of the key used in this operation.
+    index: usize,
+    /// The keystore signature algorithm, e.g. "SHA512withRSA".
+    algorithm: &'static str,
+    /// The data collected so far by a multi-part operation.
+    data: Vec<u8>,
+    /// Whether data has been added by `sign_update` or `verify_update`. A multi-part
+    /// operation can only be terminated by `sign_final` or `verify_final`.
+    multi_part: bool,
 }
 
 impl State {
-    /// Initializes the library state by fetching the keys from termux keystore.
-    /// Returns `None` if this fetch has failed.
-    fn from_bridge() -> Option<Self> {
-        let json = bridge::list_keys().ok()?;
-        let keys = key::json_to_list(json)?;
-        Some(State {
+    /// Initializes the library state of a session that is opened on the given slot with
+    /// the given flags. The keys are shared with the other sessions. The cancellation
+    /// is triggered by `C_CancelFunction` to stop the keystore calls of this session.
+    fn new(
+        slot_id: c_ulong,
+        flags: Flags,
+        keys: Arc<Keys>,
+        cancellation: Arc<Cancellation>,
+    ) -> Self {
+        // Keystore does not require logging in, so the sessions are always public.
+        let session_state = if flags.contains(Flags::RW_SESSION) {
+            SessionState::RwPublicSession
+        } else {
+            SessionState::RoPublicSession
+        };
+        State {
+            slot_id,
+            flags,
+            session_state,
             keys,
-            search_index: 0,
-            search_for_keys: false,
-            search_id: None,
-            sign_index: 0,
-        })
-    }
-
-    /// Initializes the search operation. `search_for_keys` indicates if the search
-    /// is for public/private keys, false will mean it is searching for something else,
-    /// such as certificates.
-    /// The optional field `id` limits the search to the given label.
-    pub fn find_init(&mut self, search_for_keys: bool, id: Option<String>) {
-        self.search_index = 0;
-        self.search_for_keys = search_for_keys;
-        self.search_id = id;
-    }
-
-    /// Continues a previously initiated search, returning the next key. Returns `None`
-    /// if there are no more results left.
-    pub fn find_next(&mut self) -> Option<usize> {
-        let index = self
+            cancellation,
+            search: None,
+            sign: None,
+            verify: None,
+        }
+    }
+
+    /// Returns the slot that this session is opened on.
+    pub fn slot_id(&self) -> c_ulong {
+        self.slot_id
+    }
+
+    /// Returns the session flags, which can contain `Flags::RW_SESSION` and
+    /// `Flags::SERIAL_SESSION`.
+    pub fn flags(&self) -> Flags {
+        self.flags
+    }
+
+    /// Returns the state of this session.
+    pub fn session_state(&self) -> SessionState {
+        self.session_state
+    }
+
+    /// Fetches the key that the given object belongs to.
+    pub fn get_key(&self, object: Object) -> Option<&Key> {
+        self.keys.get(object.index)
+    }
+
+    /// Initializes the search operation. Only the objects that have all the attributes
+    /// in the template, with exactly the same values, are found. The template consists
+    /// of the attribute types and their values encoded like `Object::attribute` does.
+    pub fn find_init(
+        &mut self,
+        template: &[(c_ulong, Vec<u8>)],
+        backend: &dyn Backend,
+    ) -> Result<(), ReturnValue> {
+        if self.search.is_some() {
+            return Err(ReturnValue::OperationActive);
+        }
+        let results = self
             .keys
-            .iter()
-            .enumerate()
-            .filter(|&_| self.search_for_keys)
-            .filter(|&k| match &self.search_id {
-                Some(id) => k.1.label() == id,
-                None => true,
+            .indexed()
+            .flat_map(|(index, key)| {
+                let pair = Object::pair(index).to_vec();
+                pair.into_iter().map(move |object| (object, key))
+            })
+            .filter(|(object, key)| {
+                template.iter().all(|(attribute_type, value)| {
+                    let attribute = AttributeType::try_from(*attribute_type)
+                        .ok()
+                        .and_then(|attribute_type| object.attribute(key, attribute_type, backend));
+                    attribute.as_ref() == Some(value)
+                })
             })
-            .skip(self.search_index)
-            .next()
-            .map(|(i, &_)| i);
-        if let Some(i) = index {
-            self.search_index = i + 1;
+            .map(|(object, _)| object)
+            .rev()
+            .collect();
+        self.search = Some(results);
+        Ok(())
+    }
+
+    /// Continues the search operation, returning at most `count` objects. Returns an
+    /// empty vector if there are no more results left.
+    pub fn find_next(&mut self, count: usize) -> Result<Vec<Object>, ReturnValue> {
+        let results = self
+            .search
+            .as_mut()
+            .ok_or(ReturnValue::OperationNotInitialized)?;
+        let start = results.len().saturating_sub(count);
+        Ok(results.drain(start..).rev().collect())
+    }
+
+    /// Terminates the search operation.
+    pub fn find_final(&mut self) -> Result<(), ReturnValue> {
+        self.search
+            .take()
+            .map(|_| ())
+            .ok_or(ReturnValue::OperationNotInitialized)
+    }
+
+    /// Initializes a sign operation with the provided key.
+    /// Algorithm parameter must be in the format that keystore expects (e.g. "SHA512withRSA").
+    pub fn sign_init(&mut self, index: usize, algorithm: &'static str) -> Result<(), ReturnValue> {
+        Operation::init(&mut self.sign, index, algorithm)
+    }
+
+    /// Terminates the sign operation without creating a signature, if there is one.
+    pub fn sign_cancel(&mut self) {
+        self.sign = None;
+    }
+
+    /// Adds more data to a multi-part sign operation.
+    pub fn sign_update(&mut self, data: &[u8]) -> Result<(), ReturnValue> {
+        Operation::active(&mut self.sign)?.update(data);
+        Ok(())
+    }
+
+    /// Fetches the key of the sign operation.
+    pub fn get_sign_key(&self) -> Result<&Key, ReturnValue> {
+        let operation = self
+            .sign
+            .as_ref()
+            .ok_or(ReturnValue::OperationNotInitialized)?;
+        self.keys
+            .get(operation.index)
+            .ok_or(ReturnValue::GeneralError)
+    }
+
+    /// Signs the given data in a single part, which terminates the sign operation.
+    /// Fails with `FunctionCanceled` if `C_CancelFunction` is called meanwhile, and
+    /// with `OperationActive` if `sign_update` has started a multi-part operation.
+    pub fn sign(&mut self, data: &[u8]) -> Result<Vec<u8>, ReturnValue> {
+        Operation::single_part(&mut self.sign)?;
+        self.sign_data(data)
+    }
+
+    /// Signs all the data collected by `sign_update`, which terminates the sign operation.
+    pub fn sign_final(&mut self) -> Result<Vec<u8>, ReturnValue> {
+        let data = mem::take(&mut Operation::active(&mut self.sign)?.data);
+        self.sign_data(&data)
+    }
+
+    /// Signs the data, which terminates the sign operation.
+    fn sign_data(&mut self, data: &[u8]) -> Result<Vec<u8>, ReturnValue> {
+        let operation = self
+            .sign
+            .take()
+            .ok_or(ReturnValue::OperationNotInitialized)?;
+        let key = self
+            .keys
+            .get(operation.index)
+            .ok_or(ReturnValue::GeneralError)?;
+        operation.check_input(data)?;
+        // Only the cancellations that happen during this call are relevant.
+        self.cancellation.reset();
+        let signature = key.sign(operation.algorithm, data, &self.cancellation);
+        // The key has been deleted from the keystore, so the inventory is out of date.
+        if let Err(Error::KeyNotFound(_)) = signature {
+            inventory::invalidate();
         }
-        index
+        Ok(signature?)
+    }
+
+    /// Initializes a verify operation with the provided key.
+    /// Algorithm parameter must be in the format that keystore expects (e.g. "SHA512withRSA").
+    pub fn verify_init(
+        &mut self,
+        index: usize,
+        algorithm: &'static str,
+    ) -> Result<(), ReturnValue> {
+        Operation::init(&mut self.verify, index, algorithm)
+    }
+
+    /// Terminates the verify operation without verifying a signature, if there is one.
+    pub fn verify_cancel(&mut self) {
+        self.verify = None;
+    }
+
+    /// Adds more data to a multi-part verify operation.
+    pub fn verify_update(&mut self, data: &[u8]) -> Result<(), ReturnValue> {
+        Operation::active(&mut self.verify)?.update(data);
+        Ok(())
+    }
+
+    /// Verifies the signature of the given data in a single part, which terminates the
+    /// verify operation. Returns `SignatureLenRange` if the signature does not have the
+    /// length of the signatures of the key, and `SignatureInvalid` if it does not match.
+    /// Fails with `OperationActive` if `verify_update` has started a multi-part operation.
+    pub fn verify(&mut self, data: &[u8], signature: &[u8]) -> Result<(), ReturnValue> {
+        Operation::single_part(&mut self.verify)?;
+        self.verify_data(data, signature)
+    }
+
+    /// Verifies the signature of all the data collected by `verify_update`, which
+    /// terminates the verify operation.
+    pub fn verify_final(&mut self, signature: &[u8]) -> Result<(), ReturnValue> {
+        let data = mem::take(&mut Operation::active(&mut self.verify)?.data);
+        self.verify_data(&data, signature)
+    }
+
+    /// Verifies the signature of the data, which terminates the verify operation.
+    fn verify_data(&mut self, data: &[u8], signature: &[u8]) -> Result<(), ReturnValue> {
+        let operation = self
+            .verify
+            .take()
+            .ok_or(ReturnValue::OperationNotInitialized)?;
+        let key = self
+            .keys
+            .get(operation.index)
+            .ok_or(ReturnValue::GeneralError)?;
+        operation.check_input(data)?;
+        if signature.len() != key.signature_len() {
+            Err(ReturnValue::SignatureLenRange)
+        } else if key.verify(operation.algorithm, data, signature) {
+            Ok(())
+        } else {
+            Err(ReturnValue::SignatureInvalid)
+        }
+    }
+}
+
+impl Operation {
+    /// Starts a new operation in the given slot, unless there is one already.
+    fn init(
+        slot: &mut Option<Operation>,
+        index: usize,
+        algorithm: &'static str,
+    ) -> Result<(), ReturnValue> {
+        if slot.is_some() {
+            return Err(ReturnValue::OperationActive);
+        }
+        *slot = Some(Operation {
+            index,
+            algorithm,
+            data: Vec::new(),
+            multi_part: false,
+        });
+        Ok(())
+    }
+
+    /// Checks that the data has the length that the algorithm requires, which is only
+    /// the case when the data is a hash calculated by the application.
+    fn check_input(&self, data: &[u8]) -> Result<(), ReturnValue> {
+        let len = Algorithm::from_name(self.algorithm).and_then(|algorithm| algorithm.input_len());
+        match len {
+            Some(len) if len != data.len() => Err(ReturnValue::DataLenRange),
+            _ => Ok(()),
+        }
+    }
+
+    /// Adds more data to a multi-part operation.
+    fn update(&mut self, data: &[u8]) {
+        self.data.extend_from_slice(data);
+        self.multi_part = true;
+    }
+
+    /// Returns the operation in the given slot, if there is one.
+    fn active(slot: &mut Option<Operation>) -> Result<&mut Operation, ReturnValue> {
+        slot.as_mut().ok_or(ReturnValue::OperationNotInitialized)
+    }
+
+    /// Checks that there is an operation in the given slot, which can still be
+    /// terminated in a single part. A multi-part operation is left active, so that
+    /// the application can still finish it.
+    fn single_part(slot: &mut Option<Operation>) -> Result<(), ReturnValue> {
+        match Operation::active(slot)?.multi_part {
+            true => Err(ReturnValue::OperationActive),
+            false => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the state of a session that has no keys, where an operation on the key
+    /// at index 0 fails with `GeneralError` once it reaches the key.
+    fn state() -> State {
+        State::new(
+            1,
+            Flags::SERIAL_SESSION,
+            Arc::new(Keys::default()),
+            Arc::new(Cancellation::default()),
+        )
+    }
+
+    #[test]
+    fn sign_in_a_single_part() {
+        let mut state = state();
+        state.sign_init(0, "SHA256withECDSA").unwrap();
+        assert!(matches!(
+            state.sign(b"data"),
+            Err(ReturnValue::GeneralError)
+        ));
+        assert!(matches!(
+            state.sign(b"data"),
+            Err(ReturnValue::OperationNotInitialized)
+        ));
     }
 
-    /// Fetches a key by its index.
-    pub fn get_key(&self, index: usize) -> Option<&Key> {
-        self.keys.get(index)
+    #[test]
+    fn sign_cannot_terminate_a_multi_part_operation() {
+        let mut state = state();
+        state.sign_init(0, "SHA256withECDSA").unwrap();
+        state.sign_update(b"part").unwrap();
+        assert!(matches!(
+            state.sign(b"data"),
+            Err(ReturnValue::OperationActive)
+        ));
+        // The multi-part operation is still active.
+        state.sign_update(b"part").unwrap();
+        assert!(matches!(state.sign_final(), Err(ReturnValue::GeneralError)));
+        assert!(matches!(
+            state.sign_final(),
+            Err(ReturnValue::OperationNotInitialized)
+        ));
     }
 
-    /// Sets up the store so that it can be used to sign with the provided key later.
-    pub fn sign_init(&mut self, index: usize) {
-        self.sign_index = index;
+    #[test]
+    fn verify_cannot_terminate_a_multi_part_operation() {
+        let mut state = state();
+        state.verify_init(0, "SHA256withECDSA").unwrap();
+        state.verify_update(b"part").unwrap();
+        assert!(matches!(
+            state.verify(b"data", b"sig"),
+            Err(ReturnValue::OperationActive)
+        ));
+        assert!(matches!(
+            state.verify_final(b"sig"),
+            Err(ReturnValue::GeneralError)
+        ));
+        assert!(matches!(
+            state.verify_final(b"sig"),
+            Err(ReturnValue::OperationNotInitialized)
+        ));
     }
 
-    /// Fetches the signing key which was previously set by `sign_init`.
-    pub fn get_sign_key(&self) -> Option<&Key> {
-        self.keys.get(self.sign_index)
+    #[test]
+    fn update_needs_an_operation() {
+        let mut state = state();
+        assert!(matches!(
+            state.sign_update(b"part"),
+            Err(ReturnValue::OperationNotInitialized)
+        ));
+        assert!(matches!(
+            state.verify_update(b"part"),
+            Err(ReturnValue::OperationNotInitialized)
+        ));
     }
 }
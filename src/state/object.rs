@@ -0,0 +1,260 @@
+//! Provides the objects that are exposed to the application. Each key in the
+//! keystore is exposed as two objects, a public key and a private key.
+
+use std::convert::{TryFrom, TryInto};
+use std::os::raw::c_ulong;
+
+use crate::backend::Backend;
+use crate::key::Key;
+use crate::mechanism;
+use crate::pkcs11::{AttributeType, KeyType, MechanismType, ObjectClass};
+
+/// An object that represents one half of a key pair.
+#[derive(Clone, Copy)]
+pub struct Object {
+    /// Index of the key that this object belongs to.
+    pub index: usize,
+    /// Either `ObjectClass::PublicKey` or `ObjectClass::PrivateKey`.
+    pub class: ObjectClass,
+}
+
+impl Object {
+    /// Returns the public and private key objects of the key at the given index.
+    pub fn pair(index: usize) -> [Object; 2] {
+        [
+            Object {
+                index,
+                class: ObjectClass::PublicKey,
+            },
+            Object {
+                index,
+                class: ObjectClass::PrivateKey,
+            },
+        ]
+    }
+
+    /// Decodes an object handle. The handles are derived from the key index, public
+    /// and private keys of the same key pair have consecutive handles. The handle 0
+    /// is never used as it is reserved as the invalid handle by the standard.
+    pub fn from_handle(handle: c_ulong) -> Option<Self> {
+        let handle: usize = handle.checked_sub(1)?.try_into().ok()?;
+        let class = match handle % 2 {
+            0 => ObjectClass::PublicKey,
+            _ => ObjectClass::PrivateKey,
+        };
+        Some(Object {
+            index: handle / 2,
+            class,
+        })
+    }
+
+    /// Returns the handle that represents this object.
+    pub fn handle(&self) -> Option<c_ulong> {
+        let private = match self.class {
+            ObjectClass::PrivateKey => 1,
+            _ => 0,
+        };
+        let handle = self.index.checked_mul(2)?.checked_add(private + 1)?;
+        handle.try_into().ok()
+    }
+
+    /// Returns whether the given attribute of this object holds secret key material.
+    /// Keystore never reveals these values, and the application must be told that they
+    /// are sensitive rather than missing.
+    pub fn is_sensitive(&self, key: &Key, attribute_type: AttributeType) -> bool {
+        if self.class != ObjectClass::PrivateKey {
+            return false;
+        }
+        match key {
+            Key::Rsa(_) => matches!(
+                attribute_type,
+                AttributeType::PrivateExponent
+                    | AttributeType::Prime1
+                    | AttributeType::Prime2
+                    | AttributeType::Exponent1
+                    | AttributeType::Exponent2
+                    | AttributeType::Coefficient
+            ),
+            Key::Ec(_) => matches!(attribute_type, AttributeType::Value),
+        }
+    }
+
+    /// Returns the value of the given attribute of this object, encoded the way
+    /// `C_GetAttributeValue` returns it. `key` must be the key this object belongs to.
+    /// Returns `None` if the object does not have this attribute.
+    ///
+    /// The values reflect how keystore handles the keys: they are generated on the
+    /// device, they are never extractable, and they cannot be modified, copied or
+    /// destroyed through this library. A private key only allows the mechanisms that
+    /// the backend can sign with.
+    pub fn attribute(
+        &self,
+        key: &Key,
+        attribute_type: AttributeType,
+        backend: &dyn Backend,
+    ) -> Option<Vec<u8>> {
+        let private = self.class == ObjectClass::PrivateKey;
+        let value = match attribute_type {
+            // Storage object attributes.
+            AttributeType::Class => ulong(c_ulong::try_from(self.class).ok()?),
+            AttributeType::Token => boolean(true),
+            AttributeType::Private => boolean(private),
+            AttributeType::Modifiable | AttributeType::Copyable | AttributeType::Destroyable => {
+                boolean(false)
+            }
+            AttributeType::Label => key.label().as_bytes().to_vec(),
+
+            // Key object attributes.
+            AttributeType::KeyType => {
+                let key_type = match key {
+                    Key::Rsa(_) => KeyType::Rsa,
+                    Key::Ec(_) => KeyType::Ec,
+                };
+                ulong(c_ulong::try_from(key_type).ok()?)
+            }
+            AttributeType::Id => key.id()?,
+            // Keystore does not expose the validity period, which is allowed to be empty.
+            AttributeType::StartDate | AttributeType::EndDate => Vec::new(),
+            AttributeType::Derive => boolean(false),
+            AttributeType::Local => boolean(true),
+            AttributeType::KeyGenMechanism => {
+                let mechanism = match key {
+                    Key::Rsa(_) => MechanismType::RsaPkcsKeyPairGen,
+                    Key::Ec(_) => MechanismType::EcKeyPairGen,
+                };
+                ulong(c_ulong::try_from(mechanism).ok()?)
+            }
+            AttributeType::AllowedMechanisms => {
+                let mechanisms: Vec<_> = if private {
+                    mechanism::for_signing(key, backend).collect()
+                } else {
+                    mechanism::for_key(key).to_vec()
+                };
+                let mut value = Vec::new();
+                for mechanism in mechanisms {
+                    value.extend(ulong(c_ulong::try_from(mechanism).ok()?));
+                }
+                value
+            }
+
+            // Public and private key object attributes. Keys do not have certificates,
+            // so their subject is empty.
+            AttributeType::Subject => Vec::new(),
+            AttributeType::PublicKeyInfo => match key {
+                Key::Rsa(key) => key.public_key_info_as_asn1().ok()?,
+                Key::Ec(key) => key.public_key_info_as_asn1().ok()?,
+            },
+            AttributeType::Sign => boolean(private),
+            AttributeType::Verify => boolean(!private),
+            AttributeType::Encrypt
+            | AttributeType::VerifyRecover
+            | AttributeType::Wrap
+            | AttributeType::Trusted
+                if !private =>
+            {
+                boolean(false)
+            }
+            AttributeType::Decrypt
+            | AttributeType::SignRecover
+            | AttributeType::Unwrap
+            | AttributeType::WrapWithTrusted
+            | AttributeType::AlwaysAuthenticate
+            | AttributeType::Extractable
+                if private =>
+            {
+                boolean(false)
+            }
+            AttributeType::Sensitive
+            | AttributeType::AlwaysSensitive
+            | AttributeType::NeverExtractable
+                if private =>
+            {
+                boolean(true)
+            }
+
+            // RSA key attributes.
+            AttributeType::Modulus => match key {
+                Key::Rsa(key) => key.modulus().to_vec(),
+                Key::Ec(_) => return None,
+            },
+            AttributeType::ModulusBits => match key {
+                Key::Rsa(_) if !private => ulong(key.size().try_into().ok()?),
+                _ => return None,
+            },
+            AttributeType::PublicExponent => match key {
+                Key::Rsa(key) => key.exponent().to_vec(),
+                Key::Ec(_) => return None,
+            },
+
+            // EC key attributes.
+            AttributeType::EcParams => match key {
+                Key::Ec(key) => key.params_as_asn1().ok()?,
+                Key::Rsa(_) => return None,
+            },
+            // The point is the public key itself, so it is not an attribute of private keys.
+            AttributeType::EcPoint => match key {
+                Key::Ec(key) if !private => key.point_as_asn1().ok()?,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+/// Encodes a `CK_ULONG` attribute value.
+fn ulong(value: c_ulong) -> Vec<u8> {
+    value.to_ne_bytes().to_vec()
+}
+
+/// Encodes a `CK_BBOOL` attribute value.
+fn boolean(value: bool) -> Vec<u8> {
+    vec![value as u8]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handles_are_never_zero() {
+        assert!(Object::from_handle(0).is_none());
+        for object in &Object::pair(0) {
+            assert_ne!(object.handle(), Some(0));
+        }
+    }
+
+    #[test]
+    fn pair_has_consecutive_handles() {
+        let [public, private] = Object::pair(5);
+        assert_eq!(public.handle(), Some(11));
+        assert_eq!(private.handle(), Some(12));
+    }
+
+    #[test]
+    fn handles_round_trip() {
+        for index in [0, 1, 2, 1000] {
+            for object in &Object::pair(index) {
+                let decoded = Object::from_handle(object.handle().unwrap()).unwrap();
+                assert_eq!(decoded.index, object.index);
+                assert_eq!(decoded.class, object.class);
+            }
+        }
+    }
+
+    #[test]
+    fn largest_handle_is_decoded() {
+        let object = Object::from_handle(c_ulong::MAX).unwrap();
+        assert_eq!(object.handle(), Some(c_ulong::MAX));
+    }
+
+    #[test]
+    fn index_without_handle() {
+        let [public, private] = Object::pair(usize::MAX / 2);
+        assert_eq!(public.handle(), Some(c_ulong::MAX));
+        assert!(private.handle().is_none());
+        for object in &Object::pair(usize::MAX / 2 + 1) {
+            assert!(object.handle().is_none());
+        }
+    }
+}
@@ -1,52 +1,128 @@
 //! Holds a static state store. This static store is required as the PKCS#11
 //! interface does not let the libraries to pass around a pointer. Therefore the
 //! application cannot keep track of the state, the library need to do it instead.
+//!
+//! The store is only locked briefly to look up the states. Each state has its own
+//! lock, so a slow operation in one session does not block the other sessions.
 
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
+use std::os::raw::c_ulong;
 use std::sync::{Arc, Mutex};
 
 use once_cell::sync::OnceCell;
 
-use super::State;
+use super::{inventory, State};
+use crate::bridge::Cancellation;
+use crate::library;
+use crate::pkcs11::{Flags, ReturnValue};
 
 /// The instance that holds all the states.
-static INSTANCE: OnceCell<Mutex<HashMap<u64, Arc<Mutex<State>>>>> = OnceCell::new();
+static INSTANCE: OnceCell<Mutex<HashMap<c_ulong, Entry>>> = OnceCell::new();
 
-/// Creates a new library state. Returns the index of the newly created state.
-/// Returns `None` if the initialization failed, for example
-/// if the termux keystore is not reachable.
-pub fn new() -> Option<u64> {
-    let states = INSTANCE.get_or_init(|| Mutex::new(HashMap::new()));
-    let mut states = states.lock().ok()?;
-    let keys: HashSet<u64> = states.keys().copied().collect();
-    let index = (0..u64::MAX).filter(|i| !&keys.contains(i)).next()?;
-    let state = State::from_bridge()?;
-    states.insert(index, Arc::new(Mutex::new(state)));
-    Some(index)
+/// A state in the store, along with the details of its session that are needed
+/// without locking the state.
+struct Entry {
+    slot_id: c_ulong,
+    flags: Flags,
+    cancellation: Arc<Cancellation>,
+    state: Arc<Mutex<State>>,
+}
+
+/// Creates a new library state for a session opened on the given slot with the given
+/// flags. Returns the index of the newly created state, which is never 0 as that is
+/// the invalid handle. Fails if the keys cannot be fetched, for example if the termux
+/// keystore is not reachable.
+pub fn new(slot_id: c_ulong, flags: Flags) -> Result<c_ulong, ReturnValue> {
+    // The keys are fetched before locking the store, as it may take a while.
+    let keys = inventory::get()?;
+    let cancellation = Arc::new(Cancellation::default());
+    let state = State::new(slot_id, flags, keys, Arc::clone(&cancellation));
+    library::synchronized(|| {
+        let states = INSTANCE.get_or_init(|| Mutex::new(HashMap::new()));
+        let mut states = states.lock().map_err(|_| ReturnValue::GeneralError)?;
+        let index = (1..c_ulong::MAX)
+            .find(|i| !states.contains_key(i))
+            .ok_or(ReturnValue::SessionCount)?;
+        let entry = Entry {
+            slot_id,
+            flags,
+            cancellation,
+            state: Arc::new(Mutex::new(state)),
+        };
+        states.insert(index, entry);
+        Ok(index)
+    })
 }
 
 /// Returns the state associated with the given index.
-pub fn get(index: u64) -> Option<Arc<Mutex<State>>> {
-    let states = INSTANCE.get_or_init(|| Mutex::new(HashMap::new()));
-    let states = states.lock().ok()?;
-    let state = states.get(&index)?;
-    Some(Arc::clone(state))
+pub fn get(index: c_ulong) -> Option<Arc<Mutex<State>>> {
+    library::synchronized(|| {
+        let states = INSTANCE.get_or_init(|| Mutex::new(HashMap::new()));
+        let states = states.lock().ok()?;
+        let entry = states.get(&index)?;
+        Some(Arc::clone(&entry.state))
+    })
+}
+
+/// Cancels the keystore call that is in progress for the state associated with the
+/// given index. The state itself is not locked, as the call holds its lock.
+pub fn cancel(index: c_ulong) -> Option<()> {
+    library::synchronized(|| {
+        let states = INSTANCE.get_or_init(|| Mutex::new(HashMap::new()));
+        let states = states.lock().ok()?;
+        states.get(&index)?.cancellation.cancel();
+        Some(())
+    })
 }
 
 /// Removed the state associated with the index, allowing its resources
 /// to be freed.
-pub fn remove(index: u64) -> Option<Arc<Mutex<State>>> {
-    let states = INSTANCE.get_or_init(|| Mutex::new(HashMap::new()));
-    let mut states = states.lock().ok()?;
-    states.remove(&index)
-}
-
-/// Returns the current number of open states.
-pub fn count() -> usize {
-    let states = INSTANCE.get_or_init(|| Mutex::new(HashMap::new()));
-    let states = states.lock();
-    match states {
-        Ok(states) => states.len(),
-        Err(_) => 0,
-    }
+pub fn remove(index: c_ulong) -> Option<Arc<Mutex<State>>> {
+    library::synchronized(|| {
+        let states = INSTANCE.get_or_init(|| Mutex::new(HashMap::new()));
+        let mut states = states.lock().ok()?;
+        let entry = states.remove(&index)?;
+        Some(entry.state)
+    })
+}
+
+/// Removes all the states of the sessions opened on the given slot.
+pub fn remove_slot(slot_id: c_ulong) {
+    library::synchronized(|| {
+        if let Some(states) = INSTANCE.get() {
+            if let Ok(mut states) = states.lock() {
+                states.retain(|_, entry| entry.slot_id != slot_id);
+            }
+        }
+    })
+}
+
+/// Returns the current number of open states, and how many of them belong
+/// to read/write sessions.
+pub fn count() -> (usize, usize) {
+    library::synchronized(|| {
+        let states = INSTANCE.get_or_init(|| Mutex::new(HashMap::new()));
+        let states = states.lock();
+        match states {
+            Ok(states) => {
+                let read_write = states
+                    .values()
+                    .filter(|entry| entry.flags.contains(Flags::RW_SESSION))
+                    .count();
+                (states.len(), read_write)
+            }
+            Err(_) => (0, 0),
+        }
+    })
+}
+
+/// Removes all the states, which closes all the sessions.
+pub fn clear() {
+    library::synchronized(|| {
+        if let Some(states) = INSTANCE.get() {
+            if let Ok(mut states) = states.lock() {
+                states.clear();
+            }
+        }
+    })
 }